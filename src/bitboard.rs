@@ -1,3 +1,7 @@
+use std::sync::OnceLock;
+
+use crate::board::Color;
+
 pub type Bitboard = u64;
 
 pub const A1: u64 = 1 << 0;
@@ -33,6 +37,309 @@ pub const F8: u64 = 1 << 61;
 pub const G8: u64 = 1 << 62;
 pub const H8: u64 = 1 << 63;
 
+/// Squares attacked by a piece jumping from each square with these offsets
+const fn jump_attacks(offsets: &[(i8, i8)]) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let mut i = 0;
+        while i < offsets.len() {
+            let file = (sq % 8) as i8 + offsets[i].0;
+            let rank = (sq / 8) as i8 + offsets[i].1;
+            if file >= 0 && file < 8 && rank >= 0 && rank < 8 {
+                table[sq] |= 1 << (rank * 8 + file);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+const KNIGHT_ATTACKS: [Bitboard; 64] = jump_attacks(&[
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+    (-1, -2),
+    (1, 2),
+    (1, -2),
+    (2, -1),
+    (2, 1),
+]);
+const KING_ATTACKS: [Bitboard; 64] = jump_attacks(&[
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+]);
+const WHITE_PAWN_ATTACKS: [Bitboard; 64] = jump_attacks(&[(-1, 1), (1, 1)]);
+const BLACK_PAWN_ATTACKS: [Bitboard; 64] = jump_attacks(&[(-1, -1), (1, -1)]);
+
+pub fn knight_attacks(sq: usize) -> Bitboard {
+    KNIGHT_ATTACKS[sq]
+}
+
+pub fn king_attacks(sq: usize) -> Bitboard {
+    KING_ATTACKS[sq]
+}
+
+/// Squares attacked by a pawn of `color` on `sq`
+pub fn pawn_attacks(color: Color, sq: usize) -> Bitboard {
+    match color {
+        Color::White => WHITE_PAWN_ATTACKS[sq],
+        Color::Black => BLACK_PAWN_ATTACKS[sq],
+    }
+}
+
+const ROOK_DIRS: [(i8, i8); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const BISHOP_DIRS: [(i8, i8); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+
+/// Squares attacked from `sq` by a slider moving in `dirs`, found by walking
+/// the rays until a piece of `occupied` is met. Used to fill the magic tables.
+pub fn slider_attacks_slow(sq: usize, occupied: Bitboard, dirs: &[(i8, i8)]) -> Bitboard {
+    let mut attacks = 0;
+    for (df, dr) in dirs.iter() {
+        let (mut file, mut rank) = ((sq % 8) as i8 + df, (sq / 8) as i8 + dr);
+        while (0..8).contains(&file) && (0..8).contains(&rank) {
+            let bb = 1 << (rank * 8 + file);
+            attacks |= bb;
+            if occupied & bb != 0 {
+                break;
+            }
+            file += df;
+            rank += dr;
+        }
+    }
+    attacks
+}
+
+/// Squares whose occupancy matters for a slider on `sq`, the last square of
+/// each ray is always attacked whether it is occupied or not
+fn relevant_occupancy(sq: usize, dirs: &[(i8, i8)]) -> Bitboard {
+    let mut mask = 0;
+    for (df, dr) in dirs.iter() {
+        let (mut file, mut rank) = ((sq % 8) as i8 + df, (sq / 8) as i8 + dr);
+        while (0..8).contains(&(file + df)) && (0..8).contains(&(rank + dr)) {
+            mask |= 1 << (rank * 8 + file);
+            file += df;
+            rank += dr;
+        }
+    }
+    mask
+}
+
+/// Perfect hash from the relevant occupancy of a square to its slice of the
+/// attack table: `((occupied & mask) * magic) >> shift`
+#[derive(Clone, Copy, Debug)]
+pub struct Magic {
+    pub mask: Bitboard,
+    pub magic: u64,
+    pub shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+/// xorshift64*, the magics only have to be found once so any generator works
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Magics with few bits set are found faster
+    fn sparse(&mut self) -> u64 {
+        self.next() & self.next() & self.next()
+    }
+}
+
+/// Search a magic number for `sq` by trial and error. Returns the magic and
+/// the attacks of every index, an index is never used by two occupancies with
+/// different attacks.
+pub fn find_magic(sq: usize, dirs: &[(i8, i8)], seed: u64) -> (u64, Vec<Bitboard>) {
+    let mask = relevant_occupancy(sq, dirs);
+    let bits = mask.count_ones();
+    let shift = 64 - bits;
+
+    // enumerate the subsets of the mask with the carry-rippler trick
+    let mut occupancies = Vec::with_capacity(1 << bits);
+    let mut occupied: Bitboard = 0;
+    loop {
+        occupancies.push((occupied, slider_attacks_slow(sq, occupied, dirs)));
+        occupied = occupied.wrapping_sub(mask) & mask;
+        if occupied == 0 {
+            break;
+        }
+    }
+
+    let mut rng = Rng(seed ^ (sq as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    // a slider always attacks at least one square so 0 marks an unused entry
+    let mut table = vec![0; 1 << bits];
+    loop {
+        let magic = rng.sparse();
+        if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+            continue;
+        }
+
+        table.iter_mut().for_each(|a| *a = 0);
+        let ok = occupancies.iter().all(|(occupied, attacks)| {
+            let entry = &mut table[(occupied.wrapping_mul(magic) >> shift) as usize];
+            if *entry == 0 {
+                *entry = *attacks;
+            }
+            *entry == *attacks
+        });
+        if ok {
+            return (magic, table);
+        }
+    }
+}
+
+struct SliderTables {
+    rook: [Magic; 64],
+    bishop: [Magic; 64],
+    /// Attacks of all the squares for both kinds of sliders
+    attacks: Vec<Bitboard>,
+}
+
+/// Seed of the search that found `ROOK_MAGICS` and `BISHOP_MAGICS`
+pub const MAGIC_SEED: u64 = 0x0123_4567_89ab_cdef;
+
+/// Found by `find_magic` with `MAGIC_SEED`, regenerate with `chess-cli bitboard magics`
+#[rustfmt::skip]
+const ROOK_MAGICS: [u64; 64] = [
+    0x0080002010804000, 0x4040100020004000, 0x0680088010002002, 0xc080080004100080,
+    0x0200040910200200, 0x5200380200100431, 0x0400020090210408, 0x420005002c044082,
+    0x4801800080400020, 0x2010802000400080, 0x0011002004130040, 0x0001002010010008,
+    0x0201000800110004, 0x1006000410020008, 0x0004001022010844, 0x000200020c0a9945,
+    0x3040808000204000, 0x1000848020004002, 0x0022060020408010, 0x8010808010000800,
+    0x0000828008000400, 0x1004004040020100, 0x0a00040081081002, 0x0000020000810064,
+    0x0040002080004089, 0x0000500040002000, 0x4510040020200800, 0x00004212000a0020,
+    0x0040080080800400, 0x0480040080800200, 0x4004080400100201, 0x10400a8200004514,
+    0x0080004000402000, 0x0281e01003400040, 0x1010080400200020, 0x0111000821001000,
+    0x0028020041400400, 0x0a86001002000804, 0x0304100804000102, 0x8002109402001041,
+    0x0020289040048001, 0x0000201000414000, 0x4004200041050010, 0x0048001000210100,
+    0x0008000402004040, 0x4002000408020010, 0x1080014228040010, 0x0012004302860004,
+    0x0220208000400180, 0x0000804000200080, 0x010c104100200100, 0x6085040820100100,
+    0xc000080031002500, 0x0004800400020080, 0x0c00c84102100400, 0x0080007084010200,
+    0x0000102042800303, 0x0040220410810442, 0x2440104008200101, 0x0801100021040901,
+    0x2002002088100482, 0x0101000884000241, 0x6000108110084a04, 0x2000844283040022,
+];
+
+#[rustfmt::skip]
+const BISHOP_MAGICS: [u64; 64] = [
+    0x02d020080a464444, 0x3a02080204004241, 0x5008086500200002, 0x2004104200401000,
+    0x4049114000401200, 0x0122882108009088, 0x144a080188081650, 0x5081030100924008,
+    0x80c0242102060208, 0x9049041082204500, 0x0660100400882000, 0x80e0244404809009,
+    0x1080840308000080, 0x1212229034200809, 0x1085820801080804, 0x8204048051101010,
+    0x0440040908010400, 0x0a49000430009a00, 0x0010026048802240, 0x0806800802810010,
+    0x0201020820080182, 0x004102020100a220, 0x0c14002041280880, 0x01121000808c2108,
+    0x2408200041040110, 0x00100409880800c0, 0x1208040008004018, 0x0228080090820002,
+    0x0252040002008208, 0x000841000280a002, 0x1302c48404040c00, 0x0880920020820084,
+    0x400a101000402280, 0x0801100840030802, 0x0000820100408400, 0x0200100820040400,
+    0x00400a0200842080, 0xc081081020020208, 0x2830010210005205, 0x0004305200028483,
+    0x0252082004080800, 0x8010841118006100, 0x2008918410020100, 0x0209204200800800,
+    0x1008284100400406, 0x0152020041020600, 0x00e2020811000220, 0x0082020203200214,
+    0x4800842402402200, 0x1d08804808040001, 0x0482c60846280008, 0x02a0780a05041400,
+    0x000029045054001c, 0x0414c00801010811, 0x000a821044010010, 0x0002108200810300,
+    0x400200804108a000, 0x0010425404941000, 0x043000808400a201, 0x08024c0002104418,
+    0x0080010040105100, 0x0000002020021090, 0x00040a0244140408, 0x1220011121110201,
+];
+
+impl SliderTables {
+    fn new() -> Self {
+        let mut attacks = Vec::new();
+        let mut init = |dirs: &[(i8, i8)], magics: &[u64; 64]| {
+            let mut res = [Magic {
+                mask: 0,
+                magic: 0,
+                shift: 0,
+                offset: 0,
+            }; 64];
+            for (sq, m) in res.iter_mut().enumerate() {
+                let mask = relevant_occupancy(sq, dirs);
+                *m = Magic {
+                    mask,
+                    magic: magics[sq],
+                    shift: 64 - mask.count_ones(),
+                    offset: attacks.len(),
+                };
+                attacks.resize(attacks.len() + (1 << mask.count_ones()), 0);
+
+                let mut occupied: Bitboard = 0;
+                loop {
+                    let entry = &mut attacks[m.index(occupied)];
+                    let expected = slider_attacks_slow(sq, occupied, dirs);
+                    debug_assert!(*entry == 0 || *entry == expected, "bad magic for {}", sq);
+                    *entry = expected;
+                    occupied = occupied.wrapping_sub(mask) & mask;
+                    if occupied == 0 {
+                        break;
+                    }
+                }
+            }
+            res
+        };
+        let rook = init(&ROOK_DIRS, &ROOK_MAGICS);
+        let bishop = init(&BISHOP_DIRS, &BISHOP_MAGICS);
+
+        SliderTables {
+            rook,
+            bishop,
+            attacks,
+        }
+    }
+}
+
+/// Search the magic numbers again, formatted as `ROOK_MAGICS` and `BISHOP_MAGICS`
+pub fn magics_to_string() -> String {
+    let mut str = String::new();
+    for (name, dirs) in &[("ROOK", ROOK_DIRS), ("BISHOP", BISHOP_DIRS)] {
+        str += &format!("const {}_MAGICS: [u64; 64] = [\n", name);
+        for sq in 0..64 {
+            let (magic, _) = find_magic(sq, dirs, MAGIC_SEED);
+            str += &format!("    0x{:016x},\n", magic);
+        }
+        str += "];\n";
+    }
+    str
+}
+
+/// Built on first use
+static SLIDER_TABLES: OnceLock<SliderTables> = OnceLock::new();
+
+fn slider_tables() -> &'static SliderTables {
+    SLIDER_TABLES.get_or_init(SliderTables::new)
+}
+
+/// Squares attacked by a rook on `sq`, including the first blocker of each ray
+pub fn rook_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    let tables = slider_tables();
+    tables.attacks[tables.rook[sq].index(occupied)]
+}
+
+/// Squares attacked by a bishop on `sq`, including the first blocker of each ray
+pub fn bishop_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    let tables = slider_tables();
+    tables.attacks[tables.bishop[sq].index(occupied)]
+}
+
+pub fn queen_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    rook_attacks(sq, occupied) | bishop_attacks(sq, occupied)
+}
+
 fn bitscan_forward(n: u64) -> u8 {
     assert_ne!(n, 0);
     n.trailing_zeros() as u8
@@ -88,4 +395,68 @@ fn test_bitboard_iter() {
     assert_eq!(iter.next(), Some(1));
     assert_eq!(iter.next(), Some(2));
     assert_eq!(iter.next(), None);
-}
\ No newline at end of file
+}
+#[test]
+fn test_attack_tables() {
+    let sq = |bb: Bitboard| bb.trailing_zeros() as usize;
+
+    let b3 = 1 << 17;
+    assert_eq!(b3 | C2, knight_attacks(sq(A1)));
+    assert_eq!(8, knight_attacks(27).count_ones());
+    assert_eq!(B1 | A2 | B2, king_attacks(sq(A1)));
+    assert_eq!(D1 | F1 | D2 | E2 | F2, king_attacks(sq(E1)));
+    assert_eq!(B8 | D8, pawn_attacks(Color::White, sq(C7)));
+    assert_eq!(G7, pawn_attacks(Color::Black, sq(H8)));
+    assert_eq!(0, pawn_attacks(Color::White, sq(H8)));
+}
+
+#[test]
+fn test_relevant_occupancy() {
+    // the edges are not relevant
+    assert_eq!(12, relevant_occupancy(0, &ROOK_DIRS).count_ones());
+    assert_eq!(10, relevant_occupancy(27, &ROOK_DIRS).count_ones());
+    assert_eq!(6, relevant_occupancy(0, &BISHOP_DIRS).count_ones());
+    assert_eq!(9, relevant_occupancy(27, &BISHOP_DIRS).count_ones());
+}
+
+#[test]
+fn test_magics() {
+    // the magics are the ones found by the search
+    for sq in [0, 9, 27, 63] {
+        assert_eq!(ROOK_MAGICS[sq], find_magic(sq, &ROOK_DIRS, MAGIC_SEED).0);
+        assert_eq!(BISHOP_MAGICS[sq], find_magic(sq, &BISHOP_DIRS, MAGIC_SEED).0);
+    }
+}
+
+#[test]
+fn test_find_magic() {
+    let (magic, table) = find_magic(0, &ROOK_DIRS, 42);
+    let mask = relevant_occupancy(0, &ROOK_DIRS);
+    assert_eq!(1 << 12, table.len());
+    let occupied = B1 | A2 | H1;
+    let index = (occupied & mask).wrapping_mul(magic) >> (64 - 12);
+    assert_eq!(B1 | A2, table[index as usize]);
+}
+
+#[test]
+fn test_slider_attacks() {
+    // compare with the ray walking on pseudo random occupancies
+    let mut rng = Rng(7);
+    for _ in 0..200 {
+        let occupied = rng.sparse() | rng.sparse();
+        for sq in 0..64 {
+            assert_eq!(slider_attacks_slow(sq, occupied, &ROOK_DIRS), rook_attacks(sq, occupied));
+            assert_eq!(
+                slider_attacks_slow(sq, occupied, &BISHOP_DIRS),
+                bishop_attacks(sq, occupied)
+            );
+        }
+    }
+
+    assert_eq!(B1 | A2, rook_attacks(0, B1 | A2 | H8));
+    assert_eq!(0x8040_2010_0804_0200, bishop_attacks(0, 0));
+    assert_eq!(
+        rook_attacks(12, D2) | bishop_attacks(12, D2),
+        queen_attacks(12, D2)
+    );
+}
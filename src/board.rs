@@ -1,4 +1,7 @@
-use crate::{square::Square};
+use crate::{
+    bitboard::{bitboard_iter, Bitboard},
+    square::Square,
+};
 
 pub const FILE_A: u8 = 0;
 pub const FILE_B: u8 = 1;
@@ -34,23 +37,33 @@ pub enum Color {
     Black,
 }
 
+impl PieceType {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
 impl Color {
-    pub fn opposite(&self) -> Self {
-        match *self {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
             Color::White => Color::Black,
             Color::Black => Color::White,
         }
     }
 
-    pub fn to_int(&self) -> i32 {
-        match *self {
+    pub fn to_int(self) -> i32 {
+        match self {
             Color::White => 1,
             Color::Black => -1,
         }
     }
 
-    pub fn to_string(&self) -> &'static str {
-        match *self {
+    pub fn to_string(self) -> &'static str {
+        match self {
             Color::White => "White",
             Color::Black => "Black",
         }
@@ -65,19 +78,30 @@ pub struct Piece {
 
 impl Piece {
     pub fn new(typ: PieceType, color: Color) -> Self {
-        Piece {
-            typ: typ,
-            color: color,
-        }
+        Piece { typ, color }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Pieces are stored both in a mailbox, to find the piece on a square, and in
+/// bitboards, to find the squares of a kind of piece
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Board {
-    pub board: [Option<Piece>; 64],
+    board: [Option<Piece>; 64],
+    /// Indexed by `PieceType::index`
+    pieces: [Bitboard; 6],
+    /// Indexed by `Color::index`
+    colors: [Bitboard; 2],
 }
 
 impl Board {
+    pub fn empty() -> Self {
+        Board {
+            board: [None; 64],
+            pieces: [0; 6],
+            colors: [0; 2],
+        }
+    }
+
     pub fn starting_board() -> Self {
         let mut board = [None; 64];
 
@@ -104,7 +128,11 @@ impl Board {
             board[48 + file] = Some(Piece::new(PieceType::Pawn, Color::Black));
         }
 
-        Board { board: board }
+        let mut res = Board::empty();
+        for (i, p) in board.iter().enumerate() {
+            res.set(Square::from_index(i as u8), *p);
+        }
+        res
     }
 
     /// Should I check here ?
@@ -113,9 +141,32 @@ impl Board {
     }
 
     pub fn set(&mut self, square: Square, piece: Option<Piece>) {
+        let bb = square.bitboard();
+        if let Some(p) = self.board[square.index()] {
+            self.pieces[p.typ.index()] &= !bb;
+            self.colors[p.color.index()] &= !bb;
+        }
+        if let Some(p) = piece {
+            self.pieces[p.typ.index()] |= bb;
+            self.colors[p.color.index()] |= bb;
+        }
         self.board[square.index()] = piece;
     }
 
+    /// Squares of the pieces of type `typ` and color `color`
+    pub fn pieces(&self, typ: PieceType, color: Color) -> Bitboard {
+        self.pieces[typ.index()] & self.colors[color.index()]
+    }
+
+    /// Squares of all the pieces of `color`
+    pub fn color(&self, color: Color) -> Bitboard {
+        self.colors[color.index()]
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
     pub fn to_fen(&self) -> String {
         let mut res = String::new();
         for rank in (0..8).rev() {
@@ -158,11 +209,14 @@ impl Board {
         res
     }
 
+    pub fn king_square(&self, color: Color) -> Option<Square> {
+        bitboard_iter(self.pieces(PieceType::King, color))
+            .next()
+            .map(Square::from_index)
+    }
+
     pub fn contains_ally(&self, square: Square, color: Color) -> bool {
-        match self.get(square) {
-            Some(p) if p.color == color => true,
-            _ => false,
-        }
+        matches!(self.get(square), Some(p) if p.color == color)
     }
 }
 
@@ -213,3 +267,22 @@ fn test_start() {
         })
     );
 }
+
+#[test]
+fn test_bitboards() {
+    let mut board = Board::starting_board();
+    assert_eq!(0xffff, board.color(Color::White));
+    assert_eq!(0xffff << 48, board.color(Color::Black));
+    assert_eq!(0xff00, board.pieces(PieceType::Pawn, Color::White));
+    assert_eq!(1 << 60, board.pieces(PieceType::King, Color::Black));
+
+    // capture on e7, the bitboards of both pieces are updated
+    let e7 = Square::new_nocheck(FILE_E, RANK_7);
+    board.set(e7, Some(Piece::new(PieceType::Queen, Color::White)));
+    assert_eq!(0xff << 48 & !e7.bitboard(), board.pieces(PieceType::Pawn, Color::Black));
+    assert_eq!(e7.bitboard() | 1 << 3, board.pieces(PieceType::Queen, Color::White));
+    assert_eq!(0, board.color(Color::Black) & e7.bitboard());
+    board.set(e7, None);
+    assert_eq!(31, board.occupied().count_ones());
+    assert_eq!(Some(Square::new_nocheck(FILE_E, RANK_1)), board.king_square(Color::White));
+}
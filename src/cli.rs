@@ -0,0 +1,376 @@
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    process,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use chess::{
+    bitboard::{bitboard_to_string, magics_to_string},
+    eval::breakdown,
+    game::Game,
+    perft::{divide, perft, PerftTable, REFERENCE_POSITIONS},
+    search::{Iteration, Search},
+    time::TimeManager,
+    tt::{self, TranspositionTable},
+    uci::{format_info, parse_move, GoParams},
+    Color,
+};
+
+const USAGE: &str = "usage: chess-cli <command> [options]
+
+commands:
+    perft [--fen <fen>] [--depth <n>] [--divide] [--hash <mb>]
+    bench [--depth <n>]
+    analyze [--fen <fen>] [--depth <n>] [--movetime <ms>]
+    eval [--fen <fen>]
+    play [--fen <fen>] [--depth <n>] [--movetime <ms>]
+    bitboard <u64 | magics>";
+
+const DEFAULT_PERFT_DEPTH: u32 = 5;
+const DEFAULT_BENCH_DEPTH: u32 = 5;
+const DEFAULT_PLAY_DEPTH: u32 = 6;
+
+/// Positions searched by `bench`, changing them changes the node count
+/// signature
+const BENCH_POSITIONS: &[&str] = &[
+    "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+    "2r3k1/pp3ppp/2n1b3/3p4/3P4/2NB1N2/PP3PPP/2R3K1 b - - 3 20",
+    "8/5pk1/6p1/3R4/7P/6P1/r4PK1/8 w - - 0 40",
+    "8/8/4k3/8/2K5/8/3P4/8 w - - 0 1",
+];
+
+/// `--name value` options and `--name` switches of a command, followed by
+/// positional arguments
+#[derive(Debug, Default)]
+struct Args {
+    values: HashMap<&'static str, String>,
+    switches: Vec<&'static str>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse(
+        args: &[String],
+        options: &[&'static str],
+        switches: &[&'static str],
+    ) -> Result<Self, String> {
+        let mut res = Args::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => {
+                    res.positional.push(arg.clone());
+                    continue;
+                }
+            };
+            if let Some(option) = options.iter().find(|o| **o == name) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for --{}", name))?;
+                res.values.insert(option, value.clone());
+            } else if let Some(switch) = switches.iter().find(|s| **s == name) {
+                res.switches.push(switch);
+            } else {
+                return Err(format!("unknown argument {}", arg));
+            }
+        }
+        Ok(res)
+    }
+
+    fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.values.get(name) {
+            Some(v) => v
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid value for --{}: {}", name, v)),
+            None => Ok(None),
+        }
+    }
+
+    fn has(&self, switch: &str) -> bool {
+        self.switches.contains(&switch)
+    }
+
+    fn no_positional(&self) -> Result<(), String> {
+        match self.positional.first() {
+            Some(arg) => Err(format!("unexpected argument {}", arg)),
+            None => Ok(()),
+        }
+    }
+
+    /// Position given with `--fen`, the start position by default
+    fn game(&self) -> Result<Game, String> {
+        match self.values.get("fen") {
+            Some(fen) => Game::from_fen(&mut fen.split_ascii_whitespace())
+                .ok_or_else(|| format!("invalid FEN: {}", fen)),
+            None => Ok(Game::new()),
+        }
+    }
+}
+
+fn nps(nodes: u64, elapsed: Duration) -> u64 {
+    nodes * 1000 / (elapsed.as_millis() as u64).max(1)
+}
+
+fn cmd_perft(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["fen", "depth", "hash"], &["divide"])?;
+    args.no_positional()?;
+    let mut game = args.game()?;
+    let depth = args.get("depth")?.unwrap_or(DEFAULT_PERFT_DEPTH);
+    // the hash table is disabled with a size of 0
+    let mut table = match args.get("hash")?.unwrap_or(tt::DEFAULT_SIZE_MB) {
+        0 => None,
+        mb => Some(PerftTable::new(mb)),
+    };
+
+    let start = Instant::now();
+    let nodes = if args.has("divide") {
+        let result = divide(&mut game, depth, table.as_mut());
+        for (m, nodes) in result.moves.iter() {
+            println!("{}: {}", m, nodes);
+        }
+        println!();
+        result.nodes()
+    } else {
+        perft(&mut game, depth, table.as_mut())
+    };
+    let elapsed = start.elapsed();
+
+    println!("Nodes: {}", nodes);
+    println!("Time: {} ms", elapsed.as_millis());
+    println!("Nodes/second: {}", nps(nodes, elapsed));
+    Ok(())
+}
+
+fn cmd_bench(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["depth"], &[])?;
+    args.no_positional()?;
+    let depth = args.get("depth")?.unwrap_or(DEFAULT_BENCH_DEPTH);
+
+    let fens = REFERENCE_POSITIONS
+        .iter()
+        .map(|(fen, _)| *fen)
+        .chain(BENCH_POSITIONS.iter().copied());
+
+    let start = Instant::now();
+    let mut nodes = 0;
+    for fen in fens {
+        let game = Game::from_fen(&mut fen.split_ascii_whitespace()).unwrap();
+        // every position starts from an empty table so that the count is reproducible
+        let mut tt = TranspositionTable::new(tt::DEFAULT_SIZE_MB);
+        let mut search = Search::new(&mut tt);
+        let mut last = None;
+        search.iterative_deepening(&game, depth, |it| {
+            last = Some((it.nodes, it.best_move()));
+            true
+        });
+        if let Some((n, m)) = last {
+            println!("{}: {} nodes, bestmove {}", fen, n, m);
+            nodes += n;
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!();
+    println!("Nodes searched: {}", nodes);
+    println!("Time: {} ms", elapsed.as_millis());
+    println!("Nodes/second: {}", nps(nodes, elapsed));
+    Ok(())
+}
+
+/// Search limits given with `--depth` and `--movetime`
+fn search_params(args: &Args, default_depth: Option<u32>) -> Result<GoParams, String> {
+    let params = GoParams {
+        depth: args.get("depth")?.or(default_depth),
+        movetime: args.get("movetime")?.map(Duration::from_millis),
+        ..Default::default()
+    };
+    if params.depth.is_none() && params.movetime.is_none() {
+        return Err("--depth or --movetime is required".to_string());
+    }
+    Ok(params)
+}
+
+fn search(
+    game: &Game,
+    params: &GoParams,
+    tt: &mut TranspositionTable,
+    mut on_iteration: impl FnMut(&Iteration, Duration),
+) -> Option<Iteration> {
+    let time = TimeManager::new(params, game.player, Duration::ZERO);
+    let mut search = Search::new(tt);
+    search.set_stop_condition(|nodes| time.must_stop(nodes));
+    search.iterative_deepening(game, time.max_depth(), |it| {
+        on_iteration(it, time.elapsed());
+        time.continue_search(it)
+    })
+}
+
+fn cmd_analyze(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["fen", "depth", "movetime"], &[])?;
+    args.no_positional()?;
+    let game = args.game()?;
+    let params = search_params(&args, None)?;
+
+    let mut tt = TranspositionTable::new(tt::DEFAULT_SIZE_MB);
+    let iteration = search(&game, &params, &mut tt, |it, elapsed| {
+        println!("{}", format_info(it, elapsed));
+    });
+    match iteration {
+        Some(it) => println!("bestmove {}", it.best_move()),
+        None => println!("bestmove 0000"),
+    }
+    Ok(())
+}
+
+fn cmd_eval(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["fen"], &[])?;
+    args.no_positional()?;
+    let game = args.game()?;
+    let b = breakdown(&game.board);
+    let (white, black) = (Color::White.index(), Color::Black.index());
+
+    println!("{:<14}{:>8}{:>8}{:>8}", "term", "white", "black", "total");
+    println!(
+        "{:<14}{:>8}{:>8}{:>8}",
+        "material",
+        b.material[white],
+        b.material[black],
+        b.material[white] - b.material[black]
+    );
+    println!(
+        "{:<14}{:>8}{:>8}{:>8}",
+        "piece-square",
+        b.piece_square[white],
+        b.piece_square[black],
+        b.piece_square[white] - b.piece_square[black]
+    );
+    println!("{:<14}{:>24}", "total", b.total());
+    println!("(centipawns from the point of view of white)");
+    Ok(())
+}
+
+/// Play against the engine, which answers each move read from stdin.
+/// `go` makes the engine move, so that it can play either color.
+fn cmd_play(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["fen", "depth", "movetime"], &[])?;
+    args.no_positional()?;
+    let mut game = args.game()?;
+    let params = search_params(&args, Some(DEFAULT_PLAY_DEPTH))?;
+    let mut tt = TranspositionTable::new(tt::DEFAULT_SIZE_MB);
+
+    println!("{}", game.to_fen());
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines().map_while(Result::ok) {
+        match line.trim() {
+            "" => continue,
+            "quit" => break,
+            "go" => {}
+            input => match parse_move(&game, input) {
+                Some(m) => m.make(&mut game),
+                None => {
+                    println!("illegal move {}", input);
+                    continue;
+                }
+            },
+        }
+
+        if !game.legal_moves().is_empty() {
+            let mut info = String::new();
+            let it = search(&game, &params, &mut tt, |it, elapsed| {
+                info = format_info(it, elapsed);
+            })
+            .unwrap();
+            println!("{}", info);
+            println!("engine plays {}", it.best_move());
+            it.best_move().make(&mut game);
+        }
+        println!("{}", game.to_fen());
+
+        if game.is_checkmate() {
+            println!("checkmate");
+            break;
+        }
+        if game.is_stalemate() {
+            println!("stalemate");
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn cmd_bitboard(args: &[String]) -> Result<(), String> {
+    match args {
+        [arg] if arg == "magics" => print!("{}", magics_to_string()),
+        [arg] => {
+            let n = arg
+                .parse()
+                .map_err(|_| format!("not a 64-bit integer: {}", arg))?;
+            println!("{}", bitboard_to_string(n));
+        }
+        _ => return Err("bitboard takes exactly one argument".to_string()),
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (command, args) = args.split_first().ok_or("no command")?;
+    match command.as_str() {
+        "perft" => cmd_perft(args),
+        "bench" => cmd_bench(args),
+        "analyze" => cmd_analyze(args),
+        "eval" => cmd_eval(args),
+        "play" => cmd_play(args),
+        "bitboard" => cmd_bitboard(args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("unknown command {}", command)),
+    }
+}
+
+pub fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+}
+
+#[cfg(test)]
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn test_args() {
+    let args = Args::parse(
+        &strings(&["--depth", "3", "--divide", "--fen", "8/8/8/8/8/8/8/K1k5 w - - 0 1"]),
+        &["fen", "depth"],
+        &["divide"],
+    )
+    .unwrap();
+    assert_eq!(Ok(Some(3)), args.get::<u32>("depth"));
+    assert_eq!(Ok(None), args.get::<u32>("movetime"));
+    assert!(args.has("divide"));
+    assert!(args.game().is_ok());
+
+    let parse = |a: &[&str]| Args::parse(&strings(a), &["fen", "depth"], &["divide"]);
+    assert_eq!("missing value for --depth", parse(&["--depth"]).unwrap_err());
+    assert_eq!("unknown argument --nodes", parse(&["--nodes", "3"]).unwrap_err());
+    let args = parse(&["--depth", "three"]).unwrap();
+    assert!(args.get::<u32>("depth").is_err());
+    assert!(parse(&["--fen", "not a fen"]).unwrap().game().is_err());
+
+    assert_eq!(Err("no command".to_string()), run(&[]));
+    assert!(run(&strings(&["perft", "extra"])).is_err());
+    assert!(run(&strings(&["analyze"])).is_err());
+    assert!(run(&strings(&["bitboard", "x"])).is_err());
+}
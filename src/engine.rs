@@ -0,0 +1,211 @@
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    game::Game,
+    options::Options,
+    search::Search,
+    time::TimeManager,
+    tt::{self, TranspositionTable},
+    uci::{format_info, send, GoParams},
+};
+
+/// `currmove` infos are only sent once the search has run for this long
+const CURRMOVE_DELAY: Duration = Duration::from_secs(3);
+
+/// Search running on a worker thread
+struct SearchThread {
+    handle: JoinHandle<()>,
+    /// Polled by the search, set by `stop`
+    stop: Arc<AtomicBool>,
+    /// Set during `go ponder` until `ponderhit`, the time limits are ignored
+    /// while it is set
+    pondering: Arc<AtomicBool>,
+}
+
+/// Runs the searches in the background so that the UCI loop can still answer
+/// `isready` and handle `stop` while searching
+pub struct Engine {
+    tt: Arc<Mutex<TranspositionTable>>,
+    search: Option<SearchThread>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            tt: Arc::new(Mutex::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB))),
+            search: None,
+        }
+    }
+
+    pub fn new_game(&mut self) {
+        self.clear_hash();
+    }
+
+    pub fn clear_hash(&mut self) {
+        self.stop();
+        self.tt.lock().unwrap().clear();
+    }
+
+    pub fn set_hash_size(&mut self, size_mb: usize) {
+        self.stop();
+        *self.tt.lock().unwrap() = TranspositionTable::new(size_mb);
+    }
+
+    /// Start searching `game`, the best move is printed by the worker thread
+    /// once the search is over. In infinite and ponder mode it waits for
+    /// `stop` or `ponderhit` before doing so, as required by the protocol.
+    pub fn go(&mut self, game: &Game, params: GoParams, options: &Options) {
+        self.stop();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let pondering = Arc::new(AtomicBool::new(params.ponder));
+        let tt = Arc::clone(&self.tt);
+        let game = game.clone();
+        let options = options.clone();
+
+        let handle = {
+            let stop = Arc::clone(&stop);
+            let pondering = Arc::clone(&pondering);
+            thread::spawn(move || {
+                let mut tt = tt.lock().unwrap();
+                let time = TimeManager::new(&params, game.player, options.move_overhead);
+                // the time limits apply from the ponderhit, as if the search
+                // had been started then
+                let was_pondering = Cell::new(params.ponder);
+                let timed = || {
+                    if pondering.load(Ordering::Relaxed) {
+                        return false;
+                    }
+                    if was_pondering.replace(false) {
+                        time.restart();
+                    }
+                    true
+                };
+
+                let mut search = Search::new(&mut tt);
+                search.set_multipv(options.multipv);
+                search.set_contempt(options.contempt);
+                search.set_selectivity(options.selectivity);
+                search.set_stop_condition(|nodes| {
+                    stop.load(Ordering::Relaxed) || (timed() && time.must_stop(nodes))
+                });
+                let search_moves: Vec<_> = game
+                    .legal_moves()
+                    .into_iter()
+                    .filter(|m| params.searchmoves.contains(&m.to_string()))
+                    .collect();
+                if !search_moves.is_empty() {
+                    search.set_search_moves(search_moves);
+                }
+
+                search.set_root_move_callback(|depth, m, number| {
+                    if time.elapsed() >= CURRMOVE_DELAY {
+                        send(&format!(
+                            "info depth {} currmove {} currmovenumber {}",
+                            depth, m, number
+                        ));
+                    }
+                });
+
+                let iteration = search.iterative_deepening(&game, time.max_depth(), |it| {
+                    send(&format_info(it, time.elapsed()));
+
+                    if stop.load(Ordering::Relaxed) {
+                        false
+                    } else {
+                        !timed() || time.continue_search(it)
+                    }
+                });
+
+                while !stop.load(Ordering::Relaxed)
+                    && (params.infinite || pondering.load(Ordering::Relaxed))
+                {
+                    thread::park();
+                }
+
+                send(&match iteration {
+                    Some(it) if it.pv.len() > 1 => {
+                        format!("bestmove {} ponder {}", it.pv[0], it.pv[1])
+                    }
+                    Some(it) => format!("bestmove {}", it.best_move()),
+                    None => "bestmove 0000".to_string(),
+                });
+            })
+        };
+
+        self.search = Some(SearchThread {
+            handle,
+            stop,
+            pondering,
+        });
+    }
+
+    /// Stop the current search and wait for its best move to be printed
+    pub fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop.store(true, Ordering::Relaxed);
+            search.handle.thread().unpark();
+            search.handle.join().unwrap();
+        }
+    }
+
+    /// The opponent played the expected move, the ponder search goes on as a
+    /// normal search
+    pub fn ponderhit(&mut self) {
+        if let Some(search) = &self.search {
+            search.pondering.store(false, Ordering::Relaxed);
+            search.handle.thread().unpark();
+        }
+    }
+}
+
+#[cfg(test)]
+fn is_searching(engine: &Engine) -> bool {
+    engine.search.as_ref().is_some_and(|s| !s.handle.is_finished())
+}
+
+#[test]
+fn test_stop_infinite() {
+    let mut engine = Engine::new();
+    let params = GoParams {
+        infinite: true,
+        depth: Some(1),
+        ..Default::default()
+    };
+    engine.go(&Game::new(), params, &Options::default());
+    thread::sleep(Duration::from_millis(100));
+    // The search is over but the best move is only sent after stop
+    assert!(is_searching(&engine));
+    engine.stop();
+    assert!(engine.search.is_none());
+}
+
+#[test]
+fn test_ponderhit() {
+    let mut engine = Engine::new();
+    let params = GoParams {
+        ponder: true,
+        depth: Some(1),
+        ..Default::default()
+    };
+    engine.go(&Game::new(), params, &Options::default());
+    thread::sleep(Duration::from_millis(100));
+    assert!(is_searching(&engine));
+    engine.ponderhit();
+    let search = engine.search.take().unwrap();
+    search.handle.join().unwrap();
+}
@@ -1,8 +1,9 @@
-use crate::{board::{Board, Color, Piece, PieceType}, square::Square};
+use crate::{bitboard::bitboard_iter, board::{Board, Color, Piece, PieceType}, square::Square};
 
-fn value(typ: PieceType, depth: u32) -> i32 {
+/// Material value of a piece, the king is worth more than all the other pieces together
+pub fn piece_value(typ: PieceType) -> i32 {
     match typ {
-        PieceType::King => 100000 - depth as i32,
+        PieceType::King => 100000,
         PieceType::Queen => 1000,
         PieceType::Rook => 525,
         PieceType::Bishop => 350,
@@ -48,17 +49,54 @@ pub fn piece_square_value(p: Piece, file: u8, rank: u8) -> i32 {
     }
 }
 
-pub fn evaluate(board: &Board, depth: u32) -> i32 {
+/// Evaluation from the point of view of white. Both kings are always on the
+/// board so they are not counted, checkmates are scored by the search.
+pub fn evaluate(board: &Board) -> i32 {
     // TODO: check
-    let mut val = 0;
+    breakdown(board).total()
+}
+
+/// Terms of the evaluation for each color, indexed by `Color::index`. The
+/// kings are not counted in the material.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Breakdown {
+    pub material: [i32; 2],
+    pub piece_square: [i32; 2],
+}
+
+impl Breakdown {
+    /// Evaluation from the point of view of white
+    pub fn total(&self) -> i32 {
+        self.material[0] - self.material[1] + self.piece_square[0] - self.piece_square[1]
+    }
+}
+
+pub fn breakdown(board: &Board) -> Breakdown {
+    let mut res = Breakdown::default();
 
-    for rank in 0u8..8u8 {
-        for file in 0u8..8u8 {
-            let sq = Square::new_nocheck(file, rank);
-            if let Some(p) = board.get(sq) {
-                val += p.color.to_int() * (value(p.typ, depth) + piece_square_value(p, file, rank));
-            }
+    for i in bitboard_iter(board.occupied()) {
+        let sq = Square::from_index(i);
+        let p = board.get(sq).unwrap();
+        if p.typ != PieceType::King {
+            res.material[p.color.index()] += piece_value(p.typ);
         }
+        res.piece_square[p.color.index()] += piece_square_value(p, sq.file(), sq.rank());
     }
-    val
+    res
+}
+
+#[test]
+fn test_breakdown() {
+    let board = Board::starting_board();
+    let start = breakdown(&board);
+    assert_eq!(8 * 100 + 2 * 350 + 2 * 350 + 2 * 525 + 1000, start.material[0]);
+    assert_eq!(start.material[0], start.material[1]);
+    assert_eq!(0, start.total());
+
+    let fen = "4k3/8/8/8/3N4/8/8/4K3 w - - 0 1";
+    let game = crate::game::Game::from_fen_str(fen);
+    let b = breakdown(&game.board);
+    assert_eq!([350, 0], b.material);
+    assert_eq!([20, 0], b.piece_square);
+    assert_eq!(evaluate(&game.board), b.total());
 }
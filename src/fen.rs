@@ -40,7 +40,7 @@ where
             }
             Some(c) => {
                 if let Some(p) = piece(c) {
-                    let sq = Square::new(file as u8, rank as u8)?;
+                    let sq = Square::new(file as u8, rank)?;
                     board.set(sq, Some(p));
                     file += 1;
                 }
@@ -60,7 +60,7 @@ where
 {
     let mut rank = 7u8;
 
-    let mut board = Board { board: [None; 64] };
+    let mut board = Board::empty();
 
     loop {
         ranki(chars, rank, &mut board)?;
@@ -1,5 +1,10 @@
-use crate::board::{Board, Color};
+use std::cmp::min;
+
+use crate::board::{Board, Color, Piece, PieceType, RANK_3, RANK_6};
 use crate::fen::board_from_fen;
+use crate::moves::{self, MoveList};
+use crate::square::Square;
+use crate::zobrist;
 
 pub type CastlingRights = u8;
 pub const WHITE_QUEENSIDE: CastlingRights = 0b0001;
@@ -7,17 +12,52 @@ pub const BLACK_QUEENSIDE: CastlingRights = 0b0010;
 pub const WHITE_KINGSIDE: CastlingRights = 0b0100;
 pub const BLACK_KINGSIDE: CastlingRights = 0b1000;
 
+/// State that cannot be recovered from a move when undoing it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Undo {
+    pub captured: Option<Piece>,
+    pub castling_rights: CastlingRights,
+    pub en_passant: Option<Square>,
+    pub halfmove_clock: u32,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Game {
     pub board: Board,
     pub player: Color,
     pub castling_rights: CastlingRights,
+    /// Square behind a pawn that just made a double push. `Move::make` only
+    /// sets it when an enemy pawn stands next to the pushed pawn, so it is also
+    /// left out of `to_fen` and of the hash when no capture is possible.
+    pub en_passant: Option<Square>,
+    /// Number of halfmoves since the last capture or pawn move
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    /// Zobrist key of the position, maintained by `Move::make`
+    pub hash: u64,
+    /// Keys of the positions that were reached before this one, oldest first
+    pub history: Vec<u64>,
+    /// One entry per move played with `Move::make`, popped by `Move::unmake`
+    pub undo: Vec<Undo>,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Game {
     pub fn from_fen<'a, I: Iterator<Item=&'a str>>(split: &mut I) -> Option<Self> {
         let board_str = split.next()?;
         let board = board_from_fen(&mut board_str.chars().peekable())?;
+        // the move generation needs exactly one king of each color
+        if [Color::White, Color::Black]
+            .iter()
+            .any(|&c| board.pieces(PieceType::King, c).count_ones() != 1)
+        {
+            return None;
+        }
 
         let player_str = split.next()?;
 
@@ -39,43 +79,321 @@ impl Game {
             }
         } 
 
-        split.next()?; // en passant
-        split.next()?; // halfmove counter
-        split.next()?; // fullmove counter
+        let en_passant = match split.next()? {
+            "-" => None,
+            s => {
+                let mut chars = s.chars();
+                let sq = Square::from_chars(chars.next()?, chars.next()?)?;
+                if chars.next().is_some() {
+                    return None;
+                }
+                // the square is behind a pawn of the side that just moved
+                let rank = match player {
+                    Color::White => RANK_6,
+                    Color::Black => RANK_3,
+                };
+                if sq.rank() != rank {
+                    return None;
+                }
+                // left out when no capture is possible, as `Move::make` does
+                Some(sq).filter(|&sq| moves::can_capture_en_passant(&board, sq, player))
+            }
+        };
+
+        let halfmove_clock = split.next()?.parse().ok()?;
+        let fullmove_number = split.next()?.parse().ok()?;
 
-        Some(Game {
-            board: board,
-            player: player,
-            castling_rights: castling_rights,
-        })
+        let mut game = Game {
+            board,
+            player,
+            castling_rights,
+            en_passant,
+            halfmove_clock,
+            fullmove_number,
+            hash: 0,
+            history: Vec::new(),
+            undo: Vec::new(),
+        };
+        game.hash = zobrist::hash(&game);
+
+        Some(game)
+    }
+
+    /// Parse a whole FEN string, panics if it is invalid
+    #[cfg(test)]
+    pub fn from_fen_str(fen: &str) -> Self {
+        Game::from_fen(&mut fen.split_ascii_whitespace())
+            .unwrap_or_else(|| panic!("invalid FEN {}", fen))
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut res = self.board.to_fen();
+
+        res += match self.player {
+            Color::White => " w ",
+            Color::Black => " b ",
+        };
+
+        if self.castling_rights == 0 {
+            res.push('-');
+        } else {
+            for (right, c) in &[
+                (WHITE_KINGSIDE, 'K'),
+                (WHITE_QUEENSIDE, 'Q'),
+                (BLACK_KINGSIDE, 'k'),
+                (BLACK_QUEENSIDE, 'q'),
+            ] {
+                if self.castling_rights & right != 0 {
+                    res.push(*c);
+                }
+            }
+        }
+
+        match self.en_passant {
+            Some(sq) => res += &format!(" {}", sq),
+            None => res += " -",
+        }
+
+        res += &format!(" {} {}", self.halfmove_clock, self.fullmove_number);
+        res
+    }
+
+    pub fn is_fifty_move_draw(&self) -> bool {
+        self.halfmove_clock >= 100
     }
 
     pub fn new() -> Self {
-        Game {
+        let mut game = Game {
             board: Board::starting_board(),
             player: Color::White,
             castling_rights: 0b1111,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            hash: 0,
+            history: Vec::new(),
+            undo: Vec::new(),
+        };
+        game.hash = zobrist::hash(&game);
+        game
+    }
+
+    /// Number of times the current position has already occurred
+    pub fn repetitions(&self) -> usize {
+        self.repetitions_within(self.history.len())
+    }
+
+    /// Same as `repetitions` but only looks at the last `plies` positions. Only
+    /// the positions since the last capture or pawn move can be identical.
+    pub fn repetitions_within(&self, plies: usize) -> usize {
+        self.history
+            .iter()
+            .rev()
+            .take(min(plies, self.halfmove_clock as usize))
+            .skip(1)
+            .step_by(2)
+            .filter(|&&h| h == self.hash)
+            .count()
+    }
+
+    pub fn is_threefold_repetition(&self) -> bool {
+        self.repetitions() >= 2
+    }
+
+    /// Moves that do not leave the king of the player to move in check
+    pub fn legal_moves(&self) -> MoveList {
+        moves::legal_moves(self)
+    }
+
+    pub fn is_check(&self) -> bool {
+        moves::is_in_check(&self.board, self.player)
+    }
+
+    pub fn is_checkmate(&self) -> bool {
+        self.is_check() && self.legal_moves().is_empty()
+    }
+
+    pub fn is_stalemate(&self) -> bool {
+        !self.is_check() && self.legal_moves().is_empty()
+    }
+
+    /// Pass the turn, for the null move pruning. The halfmove clock is reset
+    /// so that no repetition is detected across the null move.
+    pub fn make_null_move(&mut self) {
+        self.history.push(self.hash);
+        self.undo.push(Undo {
+            captured: None,
+            castling_rights: self.castling_rights,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+        });
+        self.hash ^= zobrist::en_passant_key(self.en_passant) ^ zobrist::en_passant_key(None);
+        self.hash ^= zobrist::side_key();
+        self.en_passant = None;
+        self.halfmove_clock = 0;
+        self.player = self.player.opposite();
+    }
+
+    pub fn unmake_null_move(&mut self) {
+        let undo = self.undo.pop().expect("no move to undo");
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.player = self.player.opposite();
+        self.hash = self.history.pop().expect("no move to undo");
+    }
+
+    /// Same as `Board::set` but also updates the hash
+    pub fn set_piece(&mut self, square: Square, piece: Option<Piece>) {
+        if let Some(p) = self.board.get(square) {
+            self.hash ^= zobrist::piece_key(p, square);
+        }
+        if let Some(p) = piece {
+            self.hash ^= zobrist::piece_key(p, square);
         }
+        self.board.set(square, piece);
     }
 }
 
 #[test]
 fn test_parse_game_start() {
     let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
-    let game = Game {
-        board: Board::starting_board(),
-        player: Color::White,
-        castling_rights: 0b1111,
-    };
-    assert_eq!(game, Game::from_fen(&mut fen.split_ascii_whitespace()).unwrap());
+    let game = Game::new();
+    assert_eq!(game, Game::from_fen_str(fen));
+
+    // one king of each color
+    let fen = "rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1";
+    assert!(Game::from_fen(&mut fen.split_ascii_whitespace()).is_none());
+    let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBKKBNR w kq - 0 1";
+    assert!(Game::from_fen(&mut fen.split_ascii_whitespace()).is_none());
 }
 
 #[test]
 fn test_parse_castling_rights() {
     let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1";
-    assert_eq!(0, Game::from_fen(&mut fen.split_ascii_whitespace()).unwrap().castling_rights);
+    assert_eq!(0, Game::from_fen_str(fen).castling_rights);
     let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kk - 0 1";
-    assert_eq!(BLACK_KINGSIDE | WHITE_KINGSIDE, Game::from_fen(&mut fen.split_ascii_whitespace()).unwrap().castling_rights);
+    assert_eq!(BLACK_KINGSIDE | WHITE_KINGSIDE, Game::from_fen_str(fen).castling_rights);
     let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
-    assert_eq!(WHITE_QUEENSIDE | BLACK_QUEENSIDE | BLACK_KINGSIDE | WHITE_KINGSIDE, Game::from_fen(&mut fen.split_ascii_whitespace()).unwrap().castling_rights);
-}
\ No newline at end of file
+    assert_eq!(WHITE_QUEENSIDE | BLACK_QUEENSIDE | BLACK_KINGSIDE | WHITE_KINGSIDE, Game::from_fen_str(fen).castling_rights);
+}
+
+#[test]
+fn test_parse_en_passant() {
+    let fen = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3";
+    let game = Game::from_fen_str(fen);
+    assert_eq!(Some(Square::from_name("e3")), game.en_passant);
+    assert_eq!(
+        "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3",
+        game.to_fen()
+    );
+
+    let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e33 0 1";
+    assert!(Game::from_fen(&mut fen.split_ascii_whitespace()).is_none());
+
+    // wrong rank for the side to move
+    let fen = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR w KQkq e3 0 3";
+    assert!(Game::from_fen(&mut fen.split_ascii_whitespace()).is_none());
+    let fen = "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq d6 0 3";
+    assert!(Game::from_fen(&mut fen.split_ascii_whitespace()).is_none());
+    let fen = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e4 0 3";
+    assert!(Game::from_fen(&mut fen.split_ascii_whitespace()).is_none());
+}
+
+#[test]
+fn test_en_passant_after_double_push() {
+    use crate::moves::Move;
+
+    // no black pawn can take on e3
+    let mut game = Game::new();
+    Move::new(&game, Square::from_name("e2"), Square::from_name("e4"), None).make(&mut game);
+    assert_eq!(None, game.en_passant);
+    assert_eq!("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1", game.to_fen());
+
+    let fen = "rnbqkbnr/ppp1pppp/8/8/3p4/8/PPPPPPPP/RNBQKBNR w KQkq - 0 3";
+    let mut game = Game::from_fen_str(fen);
+    Move::new(&game, Square::from_name("e2"), Square::from_name("e4"), None).make(&mut game);
+    assert_eq!(Some(Square::from_name("e3")), game.en_passant);
+    assert_eq!("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3", game.to_fen());
+
+    // a FEN with an en passant square no pawn can use is the same position
+    let mut game = Game::new();
+    Move::new(&game, Square::from_name("e2"), Square::from_name("e4"), None).make(&mut game);
+    let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+    let parsed = Game::from_fen_str(fen);
+    assert_eq!(None, parsed.en_passant);
+    assert_eq!(game.hash, parsed.hash);
+    assert_eq!(game.to_fen(), parsed.to_fen());
+}
+
+#[test]
+fn test_parse_counters() {
+    let fen = "8/8/8/4k3/8/8/8/4K2R b K - 11 34";
+    let game = Game::from_fen_str(fen);
+    assert_eq!(11, game.halfmove_clock);
+    assert_eq!(34, game.fullmove_number);
+    assert_eq!(fen, game.to_fen());
+
+    let fen = "8/8/8/4k3/8/8/8/4K2R b K - x 34";
+    assert!(Game::from_fen(&mut fen.split_ascii_whitespace()).is_none());
+}
+
+#[test]
+fn test_repetitions() {
+    use crate::moves::Move;
+
+    let mut game = Game::new();
+    let moves = [("g1", "f3"), ("g8", "f6"), ("f3", "g1"), ("f6", "g8")];
+
+    for (from, to) in moves.iter() {
+        Move::new(&game, Square::from_name(from), Square::from_name(to), None).make(&mut game);
+    }
+    assert_eq!(1, game.repetitions());
+    assert!(!game.is_threefold_repetition());
+
+    for (from, to) in moves.iter() {
+        Move::new(&game, Square::from_name(from), Square::from_name(to), None).make(&mut game);
+    }
+    assert_eq!(2, game.repetitions());
+    assert!(game.is_threefold_repetition());
+
+    Move::new(&game, Square::from_name("e2"), Square::from_name("e4"), None).make(&mut game);
+    Move::new(&game, Square::from_name("e7"), Square::from_name("e5"), None).make(&mut game);
+    assert_eq!(0, game.repetitions());
+    for (from, to) in moves.iter() {
+        Move::new(&game, Square::from_name(from), Square::from_name(to), None).make(&mut game);
+    }
+    assert_eq!(1, game.repetitions());
+}
+
+#[test]
+fn test_checkmate_stalemate() {
+    let mate = Game::from_fen_str("R5k1/5ppp/8/8/8/8/8/6K1 b - - 1 1");
+    assert!(mate.is_check());
+    assert!(mate.is_checkmate());
+    assert!(!mate.is_stalemate());
+
+    let stalemate = Game::from_fen_str("7k/5Q2/8/8/8/8/8/6K1 b - - 0 1");
+    assert!(!stalemate.is_check());
+    assert!(!stalemate.is_checkmate());
+    assert!(stalemate.is_stalemate());
+
+    let check = Game::from_fen_str("4k3/8/8/8/8/8/8/4R1K1 b - - 0 1");
+    assert!(check.is_check());
+    assert!(!check.is_checkmate());
+    assert!(!Game::new().is_check());
+    assert_eq!(20, Game::new().legal_moves().len());
+}
+
+#[test]
+fn test_null_move() {
+    let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2";
+    let mut game = Game::from_fen_str(fen);
+    let original = game.clone();
+
+    game.make_null_move();
+    assert_eq!(Color::Black, game.player);
+    assert_eq!(None, game.en_passant);
+    assert_eq!(zobrist::hash(&game), game.hash);
+    game.unmake_null_move();
+    assert_eq!(original, game);
+}
@@ -0,0 +1,26 @@
+//! Chess engine library: board representation, FEN parsing, move generation,
+//! evaluation and search, plus the UCI protocol used by the `chess` binary.
+
+pub mod bitboard;
+pub mod board;
+pub mod engine;
+pub mod eval;
+pub mod fen;
+pub mod game;
+pub mod log;
+pub mod movepick;
+pub mod moves;
+pub mod options;
+pub mod perft;
+pub mod search;
+pub mod see;
+pub mod square;
+pub mod time;
+pub mod tt;
+pub mod uci;
+pub mod zobrist;
+
+pub use board::{Board, Color, Piece, PieceType};
+pub use game::Game;
+pub use moves::{enumerate_moves, legal_moves, Move};
+pub use square::Square;
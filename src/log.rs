@@ -0,0 +1,86 @@
+use std::{
+    fmt,
+    fs::File,
+    io::Write,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Environment variable holding the path of the log file
+pub const LOG_ENV_VAR: &str = "CHESS_LOG";
+
+/// Log of the UCI conversation, nothing is logged until a file is opened.
+/// Failing to write to the log never stops the engine.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Log to the file at `path`, replacing the previous log file. Errors are
+/// reported on stderr and disable logging.
+pub fn open(path: &str) {
+    let file = match File::create(path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("cannot open log file {}: {}", path, e);
+            None
+        }
+    };
+    if let Ok(mut log) = LOG_FILE.lock() {
+        *log = file;
+    }
+}
+
+pub fn close() {
+    if let Ok(mut log) = LOG_FILE.lock() {
+        *log = None;
+    }
+}
+
+/// Line received from the GUI
+pub fn input(line: &str) {
+    write("<", line);
+}
+
+/// Line sent to the GUI
+pub fn output(line: &str) {
+    write(">", line);
+}
+
+/// Result of parsing the last input
+pub fn command(cmd: &impl fmt::Debug) {
+    write("=", &format!("{:?}", cmd));
+}
+
+fn write(direction: &str, line: &str) {
+    if let Ok(mut log) = LOG_FILE.lock() {
+        if let Some(file) = log.as_mut() {
+            let _ = writeln!(file, "{} {} {}", timestamp(), direction, line);
+        }
+    }
+}
+
+/// UTC time of day with milliseconds
+fn timestamp() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    format_time_of_day(millis)
+}
+
+fn format_time_of_day(millis: u128) -> String {
+    let secs = millis / 1000 % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        millis % 1000
+    )
+}
+
+#[test]
+fn test_timestamp() {
+    assert_eq!("00:00:00.000", format_time_of_day(0));
+    let millis = ((13 * 60 + 2) * 60 + 9) * 1000 + 42;
+    assert_eq!("13:02:09.042", format_time_of_day(millis));
+    // the day is ignored
+    assert_eq!("13:02:09.042", format_time_of_day(millis + 3 * 86_400_000));
+}
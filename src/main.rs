@@ -1,56 +1,95 @@
-mod board;
-mod eval;
-mod fen;
-mod game;
-mod moves;
-mod search;
-mod square;
-mod uci;
+use std::io::BufRead;
 
-use std::{
-    fs::File,
-    io::{BufRead, Write},
+use chess::{
+    engine::Engine,
+    game::Game,
+    log,
+    options::{Options, OPTIONS},
+    perft::{divide, PerftTable},
+    uci::{parse_command, send, Command},
 };
 
-use search::{best_move, perft};
-use uci::{parse_command, Command};
+/// Path of the log file given with `--log <path>` or in the `CHESS_LOG`
+/// environment variable, the command line takes precedence
+fn log_path_from_args() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--log" => match args.next() {
+                Some(path) => return Some(path),
+                None => eprintln!("missing path after --log"),
+            },
+            _ => match arg.strip_prefix("--log=") {
+                Some(path) => return Some(path.to_string()),
+                None => eprintln!("unknown argument {}", arg),
+            },
+        }
+    }
+    std::env::var(log::LOG_ENV_VAR).ok().filter(|p| !p.is_empty())
+}
 
 fn main() {
     let stdin = std::io::stdin();
-    let stdout = std::io::stdout();
 
-    let mut f = File::create("/home/wilhem/chess_log").unwrap();
+    if let Some(path) = log_path_from_args() {
+        log::open(&path);
+    }
 
     let mut game = None;
+    let mut engine = Engine::new();
+    let mut options = Options::default();
 
-    for l in stdin.lock().lines().map(|l| l.unwrap()) {
-        f.write_all(l.as_bytes()).unwrap();
-        f.write_all("\n".as_bytes()).unwrap();
+    for l in stdin.lock().lines().map_while(Result::ok) {
+        log::input(&l);
         let cmd = parse_command(&l);
-        write!(&mut f, "{:?}\n", cmd).unwrap();
+        log::command(&cmd);
         match cmd {
-            Some(Command::Uci) => stdout
-                .lock()
-                .write_all("id name chess\nid author Wilhem Barbier\nuciok\n".as_bytes())
-                .unwrap(),
-            Some(Command::IsReady) => stdout.lock().write_all("readyok\n".as_bytes()).unwrap(),
-            Some(Command::Quit) => return,
+            Some(Command::Uci) => {
+                send("id name chess");
+                send("id author Wilhem Barbier");
+                for option in OPTIONS {
+                    send(&option.to_string());
+                }
+                send("uciok");
+            }
+            Some(Command::IsReady) => send("readyok"),
+            Some(Command::Quit) => break,
             Some(Command::Position(g)) => game = Some(g),
-            Some(Command::Go(_)) => {
-                let g = game.as_ref().expect("no position");
-                let (m, score) = best_move(&g, 6).expect("no valid move");
-                let str = format!(
-                    "info score cp {}\nbestmove {}\n",
-                    score.to_string(),
-                    m.to_string()
-                );
-                stdout.lock().write_all(str.as_bytes()).unwrap();
+            Some(Command::Go(params)) => {
+                // the GUI waits for a bestmove, the start position is searched by default
+                let g = game.clone().unwrap_or_else(Game::new);
+                engine.go(&g, params, &options);
+            }
+            Some(Command::SetOption { name, value }) => {
+                match options.set(&name, value.as_deref()).map(|o| o.name) {
+                    Ok("Hash") => engine.set_hash_size(options.hash_mb),
+                    Ok("Clear Hash") => engine.clear_hash(),
+                    Ok("Debug Log File") => match options.debug_log_file.as_ref() {
+                        Some(path) => log::open(path),
+                        None => log::close(),
+                    },
+                    Ok(_) => {}
+                    Err(e) => send(&format!("info string {}", e)),
+                }
             }
+            Some(Command::Stop) => engine.stop(),
+            Some(Command::PonderHit) => engine.ponderhit(),
             Some(Command::Perft(depth)) => {
-                println!("{}", perft(depth));
+                // perft runs on this thread, the search must not print while it runs
+                engine.stop();
+                // counts the moves of the current position, the start position by default
+                let mut game = game.clone().unwrap_or_else(Game::new);
+                let mut table = PerftTable::new(options.hash_mb);
+                let result = divide(&mut game, depth, Some(&mut table));
+                for (m, nodes) in result.moves.iter() {
+                    send(&format!("{}: {}", m, nodes));
+                }
+                send(&format!("Nodes searched: {}", result.nodes()));
             }
-            Some(Command::NewGame) => {}
+            Some(Command::NewGame) => engine.new_game(),
             None => {}
         }
     }
+
+    engine.stop();
 }
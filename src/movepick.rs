@@ -0,0 +1,256 @@
+use crate::{
+    board::{Board, Color, Piece},
+    eval::piece_value,
+    game::Game,
+    moves::{Move, MoveList, MAX_MOVES},
+    search::MAX_PLY,
+    see::see_ge,
+};
+
+const HASH_MOVE_SCORE: i32 = 40_000_000;
+/// Captures and promotions that do not lose material, ordered by MVV-LVA
+/// above this score. The losing ones come after the quiet moves.
+const CAPTURE_SCORE: i32 = 20_000_000;
+const BAD_CAPTURE_SCORE: i32 = -CAPTURE_SCORE;
+const KILLER_SCORE: i32 = 10_000_000;
+const COUNTERMOVE_SCORE: i32 = KILLER_SCORE - 2;
+/// History scores stay within `[-MAX_HISTORY, MAX_HISTORY]`, below the countermove
+const MAX_HISTORY: i32 = 1 << 20;
+
+/// Most valuable victim, least valuable attacker
+pub fn mvv_lva(board: &Board, m: &Move) -> i32 {
+    let victim = m.captured(board).map_or(0, piece_value);
+    let promotion = m.promotion().map_or(0, piece_value);
+    10 * (victim + promotion) - piece_value(m.piece_type(board)) / 100
+}
+
+/// Captures and promotions are searched before the quiet moves
+pub fn is_quiet(m: &Move) -> bool {
+    !m.is_capture() && m.promotion().is_none()
+}
+
+fn piece_index(p: Piece) -> usize {
+    p.color.index() * 6 + p.typ.index()
+}
+
+/// Statistics on the quiet moves that caused beta cutoffs, used to order the
+/// moves of the following nodes
+pub struct History {
+    /// Two most recent cutoff moves at each ply
+    killers: [[Option<Move>; 2]; MAX_PLY],
+    /// Reply that refuted a move, indexed by the piece that moved and its destination
+    countermoves: [[Option<Move>; 64]; 12],
+    /// Butterfly table indexed by color, origin and destination
+    butterfly: Box<[[[i32; 64]; 64]; 2]>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            killers: [[None; 2]; MAX_PLY],
+            countermoves: [[None; 64]; 12],
+            butterfly: Box::new([[[0; 64]; 64]; 2]),
+        }
+    }
+
+    /// There are no killers past `MAX_PLY`
+    pub fn killers(&self, ply: usize) -> [Option<Move>; 2] {
+        self.killers.get(ply).copied().unwrap_or([None; 2])
+    }
+
+    /// `previous` is the last move played and the piece it moved
+    pub fn countermove(&self, previous: Option<(Move, Piece)>) -> Option<Move> {
+        let (m, p) = previous?;
+        self.countermoves[piece_index(p)][m.destination().index()]
+    }
+
+    pub fn score(&self, color: Color, m: &Move) -> i32 {
+        self.butterfly[color.index()][m.origin().index()][m.destination().index()]
+    }
+
+    /// The quiet move `m` caused a beta cutoff at `ply` after the moves of
+    /// `tried` failed low, with `depth` plies left to search
+    pub fn update(
+        &mut self,
+        color: Color,
+        ply: usize,
+        depth: u32,
+        m: Move,
+        previous: Option<(Move, Piece)>,
+        tried: &[Move],
+    ) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(m) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
+
+        if let Some((prev, p)) = previous {
+            self.countermoves[piece_index(p)][prev.destination().index()] = Some(m);
+        }
+
+        let bonus = (depth * depth) as i32;
+        self.add_history(color, &m, bonus);
+        for other in tried {
+            self.add_history(color, other, -bonus);
+        }
+    }
+
+    /// The scores decay as they get close to the limit so that recent
+    /// cutoffs still matter
+    fn add_history(&mut self, color: Color, m: &Move, bonus: i32) {
+        let entry =
+            &mut self.butterfly[color.index()][m.origin().index()][m.destination().index()];
+        let bonus = bonus.clamp(-MAX_HISTORY, MAX_HISTORY);
+        *entry += bonus - (*entry as i64 * bonus.abs() as i64 / MAX_HISTORY as i64) as i32;
+    }
+}
+
+/// Returns the moves of a node best first. All the moves are scored up front
+/// but only sorted as they are picked, since a cutoff often happens after the
+/// first few moves.
+pub struct MovePicker {
+    moves: MoveList,
+    scores: [i32; MAX_MOVES],
+    next: usize,
+}
+
+impl MovePicker {
+    /// The hash move comes first, then the captures and promotions that do
+    /// not lose material by MVV-LVA, the killers, the countermove, the quiet
+    /// moves by history and the losing captures
+    pub fn new(
+        moves: &[Move],
+        game: &Game,
+        hash_move: Option<Move>,
+        history: &History,
+        ply: usize,
+        previous: Option<(Move, Piece)>,
+    ) -> Self {
+        let killers = history.killers(ply);
+        let countermove = history.countermove(previous);
+        let mut picker = MovePicker {
+            moves: MoveList::new(),
+            scores: [0; MAX_MOVES],
+            next: 0,
+        };
+
+        for (i, m) in moves.iter().enumerate() {
+            picker.moves.push(*m);
+            picker.scores[i] = if Some(*m) == hash_move {
+                HASH_MOVE_SCORE
+            } else if !is_quiet(m) {
+                let base = if see_ge(game, *m, 0) {
+                    CAPTURE_SCORE
+                } else {
+                    BAD_CAPTURE_SCORE
+                };
+                base + mvv_lva(&game.board, m)
+            } else if Some(*m) == killers[0] {
+                KILLER_SCORE
+            } else if Some(*m) == killers[1] {
+                KILLER_SCORE - 1
+            } else if Some(*m) == countermove {
+                COUNTERMOVE_SCORE
+            } else {
+                history.score(game.player, m)
+            };
+        }
+        picker
+    }
+
+    /// Only the hash move is moved to the front, the others keep the order of
+    /// the move generator
+    #[cfg(test)]
+    pub fn unordered(moves: &[Move], hash_move: Option<Move>) -> Self {
+        let mut picker = MovePicker {
+            moves: MoveList::new(),
+            scores: [0; MAX_MOVES],
+            next: 0,
+        };
+        for (i, m) in moves.iter().enumerate() {
+            picker.moves.push(*m);
+            picker.scores[i] = if Some(*m) == hash_move { 1 } else { 0 };
+        }
+        picker
+    }
+}
+
+impl Iterator for MovePicker {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> {
+        let remaining = &self.scores[self.next..self.moves.len()];
+        // the first of the best moves, so that equal moves keep their order
+        let (best, _) = remaining
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, s)| **s)?;
+        let i = self.next + best;
+
+        self.moves[self.next..=i].rotate_right(1);
+        self.scores[self.next..=i].rotate_right(1);
+        self.next += 1;
+        Some(self.moves[self.next - 1])
+    }
+}
+
+#[test]
+fn test_pick_order() {
+    use crate::uci::parse_move;
+
+    let game = Game::from_fen_str("4k3/8/3p4/2q1r3/3P2N1/8/4P3/4K2R w K - 0 1");
+    let mv = |s: &str| parse_move(&game, s).unwrap();
+    let moves = game.legal_moves();
+    let mut history = History::new();
+
+    let previous = Some((mv("h1h2"), Piece::new(crate::PieceType::Rook, Color::White)));
+    history.update(Color::White, 3, 4, mv("g4h6"), None, &[]);
+    history.update(Color::White, 3, 4, mv("e1g1"), previous, &[mv("h1h8")]);
+    history.update(Color::White, 2, 4, mv("e1f2"), None, &[]);
+    assert_eq!([Some(mv("e1g1")), Some(mv("g4h6"))], history.killers(3));
+    assert_eq!(Some(mv("e1g1")), history.countermove(previous));
+    assert!(history.score(Color::White, &mv("h1h8")) < 0);
+
+    let picked: Vec<Move> =
+        MovePicker::new(&moves, &game, Some(mv("h1h3")), &history, 3, None).collect();
+    assert_eq!(moves.len(), picked.len());
+    // hash move, captures by victim then attacker, killers, history
+    let expected = ["h1h3", "d4c5", "d4e5", "g4e5", "e1g1", "g4h6", "e1f2"];
+    let names: Vec<String> = picked.iter().map(|m| m.to_string()).collect();
+    assert_eq!(expected, &names[..expected.len()]);
+    assert_eq!("h1h8", names.last().unwrap());
+
+    // the countermove comes after the killers
+    let picked: Vec<Move> =
+        MovePicker::new(&moves, &game, None, &history, 2, previous).collect();
+    assert_eq!(vec![mv("e1f2"), mv("e1g1")], picked[3..5].to_vec());
+}
+
+#[test]
+fn test_losing_captures_last() {
+    let game = Game::from_fen_str("4k3/2p5/3p4/8/8/8/8/3QK3 w - - 0 1");
+    let picked: Vec<Move> =
+        MovePicker::new(&game.legal_moves(), &game, None, &History::new(), 0, None).collect();
+    assert_eq!("d1d6", picked.last().unwrap().to_string());
+}
+
+#[test]
+fn test_history_bounds() {
+    let game = Game::from_fen_str("4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+    let m = crate::uci::parse_move(&game, "h1h5").unwrap();
+    let mut history = History::new();
+    for _ in 0..1000 {
+        history.update(Color::White, 0, 60, m, None, &[]);
+    }
+    let score = history.score(Color::White, &m);
+    assert!(score > 0 && score <= MAX_HISTORY);
+    assert!(score < COUNTERMOVE_SCORE);
+}
@@ -1,321 +1,551 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
 use crate::{
+    bitboard::{
+        bishop_attacks, bitboard_iter, king_attacks, knight_attacks, pawn_attacks,
+        queen_attacks, rook_attacks, Bitboard,
+    },
     board::{
-        Color, Piece, PieceType, FILE_A, FILE_B, FILE_C, FILE_D, FILE_E, FILE_F, FILE_G, FILE_H,
-        RANK_1, RANK_2, RANK_4, RANK_8,
+        Board, Color, Piece, PieceType, FILE_A, FILE_B, FILE_C, FILE_D, FILE_E, FILE_F, FILE_G,
+        FILE_H, RANK_1, RANK_2, RANK_7, RANK_8,
     },
     game::{
-        CastlingRights, Game, BLACK_KINGSIDE, BLACK_QUEENSIDE, WHITE_KINGSIDE, WHITE_QUEENSIDE,
+        CastlingRights, Game, Undo, BLACK_KINGSIDE, BLACK_QUEENSIDE, WHITE_KINGSIDE,
+        WHITE_QUEENSIDE,
     },
+    see::attackers_to,
     square::Square,
+    zobrist,
 };
 
-#[derive(Clone, Copy, Debug)]
-pub enum Move {
-    Normal {
-        from: Square,
-        to: Square,
-        piece: Piece,
-        capture: Option<Piece>,
-        promotion: Option<Piece>,
-        castling_rights: CastlingRights,
-    },
-    Castling {
-        from: Square,
-        to: Square,
-        from_rook: Square,
-        to_rook: Square,
-        color: Color,
-        castling_rights: CastlingRights,
-    },
+/// Move packed in 16 bits: the origin in bits 0-5, the destination in bits
+/// 6-11 and the kind of move in bits 12-15. The state needed to undo a move is
+/// kept on `Game::undo` instead.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move(u16);
+
+const QUIET: u16 = 0;
+const DOUBLE_PUSH: u16 = 1;
+const KING_CASTLE: u16 = 2;
+const QUEEN_CASTLE: u16 = 3;
+const CAPTURE: u16 = 4;
+const EN_PASSANT: u16 = 5;
+/// Set for promotions, the two lowest bits give the piece
+const PROMOTION: u16 = 8;
+const PROMOTION_PIECES: [PieceType; 4] = [
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+];
+
+/// Castling rights that are lost when a piece moves from or to `square`
+fn castling_rights_mask(square: Square) -> CastlingRights {
+    match square {
+        Square(FILE_A, RANK_1) => WHITE_QUEENSIDE,
+        Square(FILE_H, RANK_1) => WHITE_KINGSIDE,
+        Square(FILE_E, RANK_1) => WHITE_QUEENSIDE | WHITE_KINGSIDE,
+        Square(FILE_A, RANK_8) => BLACK_QUEENSIDE,
+        Square(FILE_H, RANK_8) => BLACK_KINGSIDE,
+        Square(FILE_E, RANK_8) => BLACK_QUEENSIDE | BLACK_KINGSIDE,
+        _ => 0,
+    }
 }
 
 impl Move {
+    fn pack(from: Square, to: Square, flags: u16) -> Self {
+        Move(from.index() as u16 | (to.index() as u16) << 6 | flags << 12)
+    }
+
+    fn flags(&self) -> u16 {
+        self.0 >> 12
+    }
+
     pub fn new(game: &Game, from: Square, to: Square, promotion: Option<PieceType>) -> Self {
         let piece = game.board.get(from).expect("the from square is empty");
-        let capture = game.board.get(to);
-
-        Move::Normal {
-            from: from,
-            to: to,
-            piece: piece,
-            capture: capture,
-            promotion: promotion.map(|typ| Piece::new(typ, piece.color)),
-            castling_rights: game.castling_rights,
+        let mut flags = if game.board.get(to).is_some() {
+            CAPTURE
+        } else if piece.typ == PieceType::Pawn && (from.rank() as i8 - to.rank() as i8).abs() == 2
+        {
+            DOUBLE_PUSH
+        } else {
+            QUIET
+        };
+        if let Some(typ) = promotion {
+            let index = PROMOTION_PIECES.iter().position(|p| *p == typ);
+            flags |= PROMOTION | index.expect("invalid promotion") as u16;
         }
+
+        Move::pack(from, to, flags)
     }
 
-    pub fn to_string(&self) -> String {
-        match *self {
-            Move::Normal {
-                from,
-                to,
-                piece: _,
-                capture: _,
-                promotion,
-                castling_rights: _,
-            } => {
-                let mut res: String = from.to_string() + &to.to_string();
-
-                if let Some(p) = promotion {
-                    res.push(match p.typ {
-                        PieceType::Queen => 'q',
-                        PieceType::Bishop => 'b',
-                        PieceType::Rook => 'r',
-                        PieceType::Knight => 'n',
-                        _ => unreachable!(),
-                    })
-                }
+    /// The captured pawn is next to `from`, on the file of `to`
+    pub fn en_passant(from: Square, to: Square) -> Self {
+        Move::pack(from, to, EN_PASSANT)
+    }
 
-                res
-            }
-            Move::Castling {
-                from,
-                to,
-                from_rook: _,
-                to_rook: _,
-                color: _,
-                castling_rights: _,
-            } => from.to_string() + &to.to_string(),
+    /// `to` is the destination of the king
+    pub fn castling(to: Square) -> Self {
+        let flags = match to.file() {
+            FILE_G => KING_CASTLE,
+            FILE_C => QUEEN_CASTLE,
+            _ => panic!("invalid castling destination: {}", to),
+        };
+        Move::pack(Square::new_nocheck(FILE_E, to.rank()), to, flags)
+    }
+
+    pub fn origin(&self) -> Square {
+        Square::from_index((self.0 & 0x3f) as u8)
+    }
+
+    pub fn destination(&self) -> Square {
+        Square::from_index((self.0 >> 6 & 0x3f) as u8)
+    }
+
+    pub fn promotion(&self) -> Option<PieceType> {
+        if self.flags() & PROMOTION != 0 {
+            Some(PROMOTION_PIECES[(self.flags() & 3) as usize])
+        } else {
+            None
         }
     }
 
-    // TODO: take game and update castling rights
+    pub fn is_capture(&self) -> bool {
+        self.flags() & CAPTURE != 0
+    }
+
+    pub fn is_en_passant(&self) -> bool {
+        self.flags() == EN_PASSANT
+    }
+
+    pub fn is_castling(&self) -> bool {
+        self.flags() == KING_CASTLE || self.flags() == QUEEN_CASTLE
+    }
+
+    /// Type of the moving piece, `board` is the position before the move
+    pub fn piece_type(&self, board: &Board) -> PieceType {
+        board.get(self.origin()).expect("the from square is empty").typ
+    }
+
+    /// Type of the captured piece, `board` is the position before the move
+    pub fn captured(&self, board: &Board) -> Option<PieceType> {
+        if self.is_en_passant() {
+            Some(PieceType::Pawn)
+        } else if self.is_capture() {
+            board.get(self.destination()).map(|p| p.typ)
+        } else {
+            None
+        }
+    }
+
+    /// Squares of the rook before and after castling
+    fn castling_rook(&self) -> (Square, Square) {
+        let rank = self.destination().rank();
+        if self.flags() == KING_CASTLE {
+            (Square::new_nocheck(FILE_H, rank), Square::new_nocheck(FILE_F, rank))
+        } else {
+            (Square::new_nocheck(FILE_A, rank), Square::new_nocheck(FILE_D, rank))
+        }
+    }
+
+    /// Square of the pawn captured en passant
+    fn en_passant_square(&self) -> Square {
+        Square::new_nocheck(self.destination().file(), self.origin().rank())
+    }
+
     pub fn make(&self, game: &mut Game) {
-        match *self {
-            Move::Normal {
-                from,
-                to,
-                piece,
-                capture: _,
-                promotion,
-                castling_rights: _,
-            } => {
-                match piece {
-                    Piece {
-                        typ: PieceType::King,
-                        color: Color::White,
-                    } => game.castling_rights &= !(WHITE_QUEENSIDE | WHITE_KINGSIDE),
-                    Piece {
-                        typ: PieceType::King,
-                        color: Color::Black,
-                    } => game.castling_rights &= !(BLACK_QUEENSIDE | BLACK_KINGSIDE),
-                    Piece {
-                        typ: PieceType::Rook,
-                        color: Color::Black,
-                    } => match from {
-                        Square(FILE_A, RANK_8) => game.castling_rights &= !BLACK_QUEENSIDE,
-                        Square(FILE_H, RANK_8) => game.castling_rights &= !BLACK_KINGSIDE,
-                        _ => {}
-                    },
-                    Piece {
-                        typ: PieceType::Rook,
-                        color: Color::White,
-                    } => match from {
-                        Square(FILE_A, RANK_1) => game.castling_rights &= !WHITE_QUEENSIDE,
-                        Square(FILE_H, RANK_1) => game.castling_rights &= !WHITE_KINGSIDE,
-                        _ => {}
-                    },
-                    _ => {}
-                }
-                game.board.set(from, None);
+        let (from, to) = (self.origin(), self.destination());
+        let piece = game.board.get(from).expect("the from square is empty");
+        let color = piece.color;
+        let captured = if self.is_en_passant() {
+            game.board.get(self.en_passant_square())
+        } else {
+            game.board.get(to)
+        };
+
+        game.history.push(game.hash);
+        game.undo.push(Undo {
+            captured,
+            castling_rights: game.castling_rights,
+            en_passant: game.en_passant,
+            halfmove_clock: game.halfmove_clock,
+        });
+        game.hash ^= zobrist::castling_key(game.castling_rights);
+        game.hash ^= zobrist::en_passant_key(game.en_passant);
 
-                let piece = match promotion {
-                    Some(x) => x,
-                    None => piece,
-                };
+        game.castling_rights &= !(castling_rights_mask(from) | castling_rights_mask(to));
 
-                game.board.set(to, Some(piece));
-            }
-            Move::Castling {
-                from,
-                to,
-                from_rook,
-                to_rook,
-                color,
-                castling_rights: _,
-            } => {
-                match color {
-                    Color::White => game.castling_rights &= !(WHITE_QUEENSIDE | WHITE_KINGSIDE),
-                    Color::Black => game.castling_rights &= !(BLACK_QUEENSIDE | BLACK_KINGSIDE),
-                }
-                game.board.set(from, None);
-                game.board.set(from_rook, None);
-                game.board.set(to, Some(Piece::new(PieceType::King, color)));
-                game.board
-                    .set(to_rook, Some(Piece::new(PieceType::Rook, color)));
+        if piece.typ == PieceType::Pawn || captured.is_some() {
+            game.halfmove_clock = 0;
+        } else {
+            game.halfmove_clock += 1;
+        }
+
+        // the en passant square is only set when a capture is possible so
+        // that it does not prevent the detection of repetitions
+        game.en_passant = None;
+        if self.flags() == DOUBLE_PUSH {
+            let behind = Square::new_nocheck(from.file(), (from.rank() + to.rank()) / 2);
+            if can_capture_en_passant(&game.board, behind, color.opposite()) {
+                game.en_passant = Some(behind);
             }
         }
 
+        if self.is_castling() {
+            let (from_rook, to_rook) = self.castling_rook();
+            game.set_piece(from_rook, None);
+            game.set_piece(to_rook, Some(Piece::new(PieceType::Rook, color)));
+        } else if self.is_en_passant() {
+            game.set_piece(self.en_passant_square(), None);
+        }
+
+        let piece = match self.promotion() {
+            Some(typ) => Piece::new(typ, color),
+            None => piece,
+        };
+        game.set_piece(from, None);
+        game.set_piece(to, Some(piece));
+
+        if game.player == Color::Black {
+            game.fullmove_number += 1;
+        }
         game.player = game.player.opposite();
+
+        game.hash ^= zobrist::castling_key(game.castling_rights);
+        game.hash ^= zobrist::en_passant_key(game.en_passant);
+        game.hash ^= zobrist::side_key();
+        debug_assert_eq!(game.hash, zobrist::hash(game));
     }
 
     pub fn unmake(&self, game: &mut Game) {
-        match *self {
-            Move::Normal {
-                from,
-                to,
-                piece,
-                capture,
-                promotion: _,
-                castling_rights,
-            } => {
-                game.castling_rights = castling_rights;
-                game.board.set(from, Some(piece));
-                game.board.set(to, capture);
-            }
-            Move::Castling {
-                from,
-                to,
-                from_rook,
-                to_rook,
-                color,
-                castling_rights,
-            } => {
-                game.castling_rights = castling_rights;
-                game.board
-                    .set(from, Some(Piece::new(PieceType::King, color)));
-                game.board.set(to, None);
-                game.board
-                    .set(from_rook, Some(Piece::new(PieceType::Rook, color)));
-                game.board.set(to_rook, None);
+        let (from, to) = (self.origin(), self.destination());
+        let undo = game.undo.pop().expect("no move to undo");
+
+        game.player = game.player.opposite();
+        if game.player == Color::Black {
+            game.fullmove_number -= 1;
+        }
+        let color = game.player;
+
+        let piece = match self.promotion() {
+            Some(_) => Some(Piece::new(PieceType::Pawn, color)),
+            None => game.board.get(to),
+        };
+        game.board.set(from, piece);
+
+        if self.is_castling() {
+            let (from_rook, to_rook) = self.castling_rook();
+            game.board.set(to, None);
+            game.board.set(to_rook, None);
+            game.board.set(from_rook, Some(Piece::new(PieceType::Rook, color)));
+        } else if self.is_en_passant() {
+            game.board.set(to, None);
+            game.board.set(self.en_passant_square(), undo.captured);
+        } else {
+            game.board.set(to, undo.captured);
+        }
+
+        game.castling_rights = undo.castling_rights;
+        game.en_passant = undo.en_passant;
+        game.halfmove_clock = undo.halfmove_clock;
+        game.hash = game.history.pop().expect("no move to undo");
+        debug_assert_eq!(game.hash, zobrist::hash(game));
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.origin(), self.destination())?;
+
+        if let Some(typ) = self.promotion() {
+            let c = match typ {
+                PieceType::Queen => 'q',
+                PieceType::Bishop => 'b',
+                PieceType::Rook => 'r',
+                PieceType::Knight => 'n',
+                _ => unreachable!(),
+            };
+            write!(f, "{}", c)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Move({})", self)
+    }
+}
+
+/// Maximum number of moves in a `MoveList`, no position has more legal moves
+pub const MAX_MOVES: usize = 256;
+
+/// Fixed capacity list of moves stored inline, so that move generation does
+/// not allocate
+#[derive(Clone, Copy)]
+pub struct MoveList {
+    moves: [Move; MAX_MOVES],
+    len: usize,
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        MoveList {
+            moves: [Move(0); MAX_MOVES],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, m: Move) {
+        self.moves[self.len] = m;
+        self.len += 1;
+    }
+
+    /// Keep the moves for which `f` returns true, in the same order
+    pub fn retain(&mut self, mut f: impl FnMut(&Move) -> bool) {
+        let mut len = 0;
+        for i in 0..self.len {
+            if f(&self.moves[i]) {
+                self.moves[len] = self.moves[i];
+                len += 1;
             }
         }
+        self.len = len;
+    }
+}
 
-        game.player = game.player.opposite();
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Move] {
+        &mut self.moves[..self.len]
+    }
+}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = std::iter::Take<std::array::IntoIter<Move, MAX_MOVES>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIterator::into_iter(self.moves).take(self.len)
     }
 }
 
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Debug for MoveList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Returns true if the king of `color` is attacked
+pub fn is_in_check(board: &Board, color: Color) -> bool {
+    match board.king_square(color) {
+        Some(sq) => is_square_attacked(board, sq, color.opposite()),
+        None => false,
+    }
+}
+
+/// Returns true if a piece of color `by` attacks `square`
+pub fn is_square_attacked(board: &Board, square: Square, by: Color) -> bool {
+    // a pawn of `by` attacks the square if a pawn of the other color on the
+    // square would attack it
+    let index = square.index();
+    if pawn_attacks(by.opposite(), index) & board.pieces(PieceType::Pawn, by) != 0
+        || knight_attacks(index) & board.pieces(PieceType::Knight, by) != 0
+        || king_attacks(index) & board.pieces(PieceType::King, by) != 0
+    {
+        return true;
+    }
+
+    let occupied = board.occupied();
+    let queens = board.pieces(PieceType::Queen, by);
+    rook_attacks(index, occupied) & (board.pieces(PieceType::Rook, by) | queens) != 0
+        || bishop_attacks(index, occupied) & (board.pieces(PieceType::Bishop, by) | queens) != 0
+}
+
+/// A pawn of `color` stands next to the pawn that just moved through `behind`
+pub fn can_capture_en_passant(board: &Board, behind: Square, color: Color) -> bool {
+    pawn_attacks(color.opposite(), behind.index()) & board.pieces(PieceType::Pawn, color) != 0
+}
+
 /// Generate pseudo legal moves
-pub fn enumerate_moves(game: &Game) -> Vec<Move> {
-    let mut moves = Vec::with_capacity(1000);
-    let mut has_king = false;
-    for rank in 0..8 {
-        for file in 0..8 {
-            let sq = Square::new_nocheck(file, rank);
-            if let Some(piece) = game.board.get(sq) {
-                if piece.color == game.player {
-                    match piece.typ {
-                        PieceType::King => {
-                            has_king = true;
-                            enumerate_king(game, game.player, sq, &mut moves);
-                        }
-                        PieceType::Queen => enumerate_queen(game, game.player, sq, &mut moves),
-                        PieceType::Rook => enumerate_rook(game, game.player, sq, &mut moves),
-                        PieceType::Bishop => enumerate_bishop(game, game.player, sq, &mut moves),
-                        PieceType::Knight => enumerate_knight(game, game.player, sq, &mut moves),
-                        PieceType::Pawn => enumerate_pawn(game, game.player, sq, &mut moves),
-                    }
-                }
-            }
+pub fn enumerate_moves(game: &Game) -> MoveList {
+    let mut moves = MoveList::new();
+    let color = game.player;
+    for i in bitboard_iter(game.board.color(color)) {
+        let sq = Square::from_index(i);
+        match game.board.get(sq).unwrap().typ {
+            PieceType::King => enumerate_king(game, color, sq, &mut moves),
+            PieceType::Queen => enumerate_queen(game, color, sq, &mut moves),
+            PieceType::Rook => enumerate_rook(game, color, sq, &mut moves),
+            PieceType::Bishop => enumerate_bishop(game, color, sq, &mut moves),
+            PieceType::Knight => enumerate_knight(game, color, sq, &mut moves),
+            PieceType::Pawn => enumerate_pawn(game, color, sq, &mut moves),
         }
     }
 
     enumerate_castlings(game, &mut moves);
 
-    if !has_king {
-        eprintln!("{} has no king: {}", game.player.to_string(), game.board.to_fen());
+    if game.board.pieces(PieceType::King, color) == 0 {
+        eprintln!("{} has no king: {}", game.player.to_string(), game.to_fen());
         unreachable!();
     }
 
     moves
 }
 
-fn enumerate_castlings(game: &Game, moves: &mut Vec<Move>) {
-    if game.castling_rights | WHITE_QUEENSIDE != 0
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_B, RANK_1))
-            .is_none()
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_C, RANK_1))
-            .is_none()
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_D, RANK_1))
-            .is_none()
-    {
-        moves.push(Move::Castling {
-            from: Square::new_nocheck(FILE_E, RANK_1),
-            to: Square::new_nocheck(FILE_C, RANK_1),
-            from_rook: Square::new_nocheck(FILE_A, RANK_1),
-            to_rook: Square::new_nocheck(FILE_D, RANK_1),
-            color: Color::White,
-            castling_rights: game.castling_rights,
-        });
+/// Squares strictly between `a` and `b` if they are on the same line, the
+/// line is cut at the first piece of `occupied`
+fn between(a: usize, b: usize, occupied: Bitboard) -> Bitboard {
+    let (sa, sb) = (Square::from_index(a as u8), Square::from_index(b as u8));
+    let dx = sb.file() as i8 - sa.file() as i8;
+    let dy = sb.rank() as i8 - sa.rank() as i8;
+    if dx == 0 || dy == 0 {
+        rook_attacks(a, occupied) & rook_attacks(b, occupied)
+    } else if dx.abs() == dy.abs() {
+        bishop_attacks(a, occupied) & bishop_attacks(b, occupied)
+    } else {
+        0
+    }
+}
+
+/// Generate the legal moves. Only the king and the pieces that are pinned or
+/// can address a check are restricted, so no move has to be played.
+pub fn legal_moves(game: &Game) -> MoveList {
+    let board = &game.board;
+    let color = game.player;
+    let them = color.opposite();
+    let mut moves = enumerate_moves(game);
+    let king = board.king_square(color).expect("the player has no king");
+    let k = king.index();
+
+    let occupied = board.occupied();
+    let ours = board.color(color);
+    let queens = board.pieces(PieceType::Queen, them);
+    let rooks = board.pieces(PieceType::Rook, them) | queens;
+    let bishops = board.pieces(PieceType::Bishop, them) | queens;
+
+    let checkers = attackers_to(board, king, occupied) & board.color(them);
+    // squares where a piece can capture or block the checking piece
+    let evasions = match checkers.count_ones() {
+        0 => !0,
+        1 => checkers | between(k, checkers.trailing_zeros() as usize, occupied),
+        _ => 0,
+    };
+
+    // our pieces standing alone between the king and a slider, the slider is
+    // found by looking through them from the king
+    let rook_blockers = rook_attacks(k, occupied) & ours;
+    let bishop_blockers = bishop_attacks(k, occupied) & ours;
+    let pinners = rook_attacks(k, occupied ^ rook_blockers) & rooks
+        | bishop_attacks(k, occupied ^ bishop_blockers) & bishops;
+    let mut pinned = 0;
+    for p in bitboard_iter(pinners) {
+        pinned |= between(k, p as usize, occupied) & ours;
     }
 
-    if game.castling_rights | WHITE_KINGSIDE != 0
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_F, RANK_1))
-            .is_none()
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_G, RANK_1))
-            .is_none()
+    // the king must not stay on the line of a slider when moving away from it
+    let without_king = occupied ^ king.bitboard();
+
+    moves.retain(|m| {
+        let (from, to) = (m.origin(), m.destination());
+        if m.is_castling() {
+            // castling out of and through check is already excluded
+            true
+        } else if from == king {
+            attackers_to(board, to, without_king) & board.color(them) == 0
+        } else if m.is_en_passant() {
+            // removing two pawns from the same rank can uncover a check
+            let captured = m.en_passant_square().bitboard();
+            let after = occupied ^ from.bitboard() ^ captured | to.bitboard();
+            attackers_to(board, king, after) & board.color(them) == 0
+        } else {
+            let dx = to.file() as i8 - king.file() as i8;
+            let dy = to.rank() as i8 - king.rank() as i8;
+            let pin_x = from.file() as i8 - king.file() as i8;
+            let pin_y = from.rank() as i8 - king.rank() as i8;
+            let keeps_pin = pinned & from.bitboard() == 0 || dx * pin_y == dy * pin_x;
+            evasions & to.bitboard() != 0 && keeps_pin
+        }
+    });
+
+    moves
+}
+
+fn enumerate_castlings(game: &Game, moves: &mut MoveList) {
+    let color = game.player;
+    let (rank, kingside, queenside) = match color {
+        Color::White => (RANK_1, WHITE_KINGSIDE, WHITE_QUEENSIDE),
+        Color::Black => (RANK_8, BLACK_KINGSIDE, BLACK_QUEENSIDE),
+    };
+
+    let king_sq = Square::new_nocheck(FILE_E, rank);
+    if game.castling_rights & (kingside | queenside) == 0
+        || game.board.get(king_sq) != Some(Piece::new(PieceType::King, color))
+        || is_square_attacked(&game.board, king_sq, color.opposite())
     {
-        moves.push(Move::Castling {
-            from: Square::new_nocheck(FILE_E, RANK_1),
-            to: Square::new_nocheck(FILE_G, RANK_1),
-            from_rook: Square::new_nocheck(FILE_H, RANK_1),
-            to_rook: Square::new_nocheck(FILE_F, RANK_1),
-            color: Color::White,
-            castling_rights: game.castling_rights,
-        });
+        return;
     }
 
-    if game.castling_rights | BLACK_QUEENSIDE != 0
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_B, RANK_8))
-            .is_none()
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_C, RANK_8))
-            .is_none()
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_D, RANK_8))
-            .is_none()
+    let rook = Some(Piece::new(PieceType::Rook, color));
+    let empty = |file| game.board.get(Square::new_nocheck(file, rank)).is_none();
+    // the king may not castle out of, through or into check
+    let safe = |file| {
+        !is_square_attacked(
+            &game.board,
+            Square::new_nocheck(file, rank),
+            color.opposite(),
+        )
+    };
+
+    if game.castling_rights & kingside != 0
+        && game.board.get(Square::new_nocheck(FILE_H, rank)) == rook
+        && empty(FILE_F)
+        && empty(FILE_G)
+        && safe(FILE_F)
+        && safe(FILE_G)
     {
-        moves.push(Move::Castling {
-            from: Square::new_nocheck(FILE_E, RANK_8),
-            to: Square::new_nocheck(FILE_C, RANK_8),
-            from_rook: Square::new_nocheck(FILE_A, RANK_8),
-            to_rook: Square::new_nocheck(FILE_D, RANK_8),
-            color: Color::Black,
-            castling_rights: game.castling_rights,
-        });
+        moves.push(Move::castling(Square::new_nocheck(FILE_G, rank)));
     }
 
-    if game.castling_rights | BLACK_KINGSIDE != 0
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_F, RANK_8))
-            .is_none()
-        && game
-            .board
-            .get(Square::new_nocheck(FILE_G, RANK_8))
-            .is_none()
+    if game.castling_rights & queenside != 0
+        && game.board.get(Square::new_nocheck(FILE_A, rank)) == rook
+        && empty(FILE_B)
+        && empty(FILE_C)
+        && empty(FILE_D)
+        && safe(FILE_C)
+        && safe(FILE_D)
     {
-        moves.push(Move::Castling {
-            from: Square::new_nocheck(FILE_E, RANK_1),
-            to: Square::new_nocheck(FILE_G, RANK_1),
-            from_rook: Square::new_nocheck(FILE_H, RANK_8),
-            to_rook: Square::new_nocheck(FILE_F, RANK_8),
-            color: Color::Black,
-            castling_rights: game.castling_rights,
-        });
+        moves.push(Move::castling(Square::new_nocheck(FILE_C, rank)));
     }
 }
 
-fn enumerate_promotions(
-    game: &Game,
-    from: Square,
-    to: Square,
-    moves: &mut Vec<Move>,
-) {
+fn enumerate_promotions(game: &Game, from: Square, to: Square, moves: &mut MoveList) {
     for typ in &[
         PieceType::Queen,
         PieceType::Knight,
@@ -326,26 +556,25 @@ fn enumerate_promotions(
     }
 }
 
-fn enumerate_pawn(game: &Game, color: Color, from: Square, moves: &mut Vec<Move>) {
-    let off_rank = match color {
-        Color::White => 1,
-        Color::Black => -1,
+fn enumerate_pawn(game: &Game, color: Color, from: Square, moves: &mut MoveList) {
+    let (off_rank, start_rank, promotion_rank) = match color {
+        Color::White => (1, RANK_2, RANK_8),
+        Color::Black => (-1, RANK_7, RANK_1),
     };
 
     let simple = (0, off_rank);
 
     if let Some(simple_sq) = from.offset(simple) {
         if game.board.get(simple_sq).is_none() {
-            if simple_sq.rank() == RANK_1 && color == Color::Black
-                || simple_sq.rank() == RANK_8 && color == Color::White
-            {
+            if simple_sq.rank() == promotion_rank {
                 enumerate_promotions(game, from, simple_sq, moves);
             } else {
                 moves.push(Move::new(game, from, simple_sq, None));
             }
 
-            if from.rank() == RANK_2 {
-                let double_sq = Square::new_nocheck(from.file(), RANK_4);
+            if from.rank() == start_rank {
+                let double_sq =
+                    Square::new_nocheck(from.file(), (simple_sq.rank() as i8 + off_rank) as u8);
                 if game.board.get(double_sq).is_none() {
                     moves.push(Move::new(game, from, double_sq, None));
                 }
@@ -353,105 +582,201 @@ fn enumerate_pawn(game: &Game, color: Color, from: Square, moves: &mut Vec<Move>
         }
     }
 
-    let captures_off = [(-1, off_rank), (1, off_rank)];
-    for off in captures_off.iter() {
-        if let Some(sq) = from.offset(*off) {
-            match game.board.get(sq) {
-                Some(p) if p.color != color => {
-                    if (sq.rank() == RANK_1 && color == Color::Black)
-                        || (sq.rank() == RANK_8 && color == Color::White)
-                    {
-                        enumerate_promotions(game, from, sq, moves);
-                    } else {
-                        moves.push(Move::new(game, from, sq, None))
-                    }
-                }
-                _ => {}
-            }
+    let attacks = pawn_attacks(color, from.index());
+    for i in bitboard_iter(attacks & game.board.color(color.opposite())) {
+        let sq = Square::from_index(i);
+        if sq.rank() == promotion_rank {
+            enumerate_promotions(game, from, sq, moves);
+        } else {
+            moves.push(Move::new(game, from, sq, None))
         }
     }
-}
-
-fn enumerate_king(game: &Game, color: Color, from: Square, moves: &mut Vec<Move>) {
-    for i in -1..=1 {
-        for j in -1..=1 {
-            if i == 0 && j == 0 {
-                continue;
-            }
-
-            if let Some(sq) = from.offset((i, j)) {
-                if !game.board.contains_ally(sq, color) {
-                    moves.push(Move::new(game, from, sq, None));
-                }
-            }
+    if let Some(sq) = game.en_passant {
+        if attacks & sq.bitboard() != 0 {
+            moves.push(Move::en_passant(from, sq));
         }
     }
 }
 
-fn enumerate_straight_line(
-    game: &Game,
-    color: Color,
-    from: Square,
-    moves: &mut Vec<Move>,
-    dir: (i8, i8),
-) {
-    let mut i = 1i8;
-    while let Some(sq) = from.offset((i * dir.0, i * dir.1)) {
-        match game.board.get(sq) {
-            Some(p) if p.color == color => break,
-            Some(p) if p.color != color => {
-                moves.push(Move::new(game, from, sq, None));
-                break;
-            }
-            None => moves.push(Move::new(game, from, sq, None)),
-            Some(_) => unreachable!(),
-        }
-        i += 1;
+/// Add a move from `from` to each of the `targets`
+fn enumerate_targets(game: &Game, from: Square, targets: Bitboard, moves: &mut MoveList) {
+    for i in bitboard_iter(targets) {
+        moves.push(Move::new(game, from, Square::from_index(i), None));
     }
 }
 
-fn enumerate_queen(game: &Game, color: Color, from: Square, moves: &mut Vec<Move>) {
-    for i in -1..=1 {
-        for j in -1..=1 {
-            if i == 0 && j == 0 {
-                continue;
-            }
-            enumerate_straight_line(game, color, from, moves, (i, j));
-        }
+fn enumerate_king(game: &Game, color: Color, from: Square, moves: &mut MoveList) {
+    let targets = king_attacks(from.index()) & !game.board.color(color);
+    enumerate_targets(game, from, targets, moves);
+}
+
+fn enumerate_queen(game: &Game, color: Color, from: Square, moves: &mut MoveList) {
+    let targets = queen_attacks(from.index(), game.board.occupied()) & !game.board.color(color);
+    enumerate_targets(game, from, targets, moves);
+}
+
+fn enumerate_rook(game: &Game, color: Color, from: Square, moves: &mut MoveList) {
+    let targets = rook_attacks(from.index(), game.board.occupied()) & !game.board.color(color);
+    enumerate_targets(game, from, targets, moves);
+}
+
+fn enumerate_bishop(game: &Game, color: Color, from: Square, moves: &mut MoveList) {
+    let targets = bishop_attacks(from.index(), game.board.occupied()) & !game.board.color(color);
+    enumerate_targets(game, from, targets, moves);
+}
+
+fn enumerate_knight(game: &Game, color: Color, from: Square, moves: &mut MoveList) {
+    let targets = knight_attacks(from.index()) & !game.board.color(color);
+    enumerate_targets(game, from, targets, moves);
+}
+
+#[test]
+fn test_make_unmake_counters() {
+    let fen = "4k3/8/8/8/8/8/4P3/4K1NR b K - 7 20";
+    let mut game = Game::from_fen_str(fen);
+
+    let black = Move::new(&game, Square::from_name("e8"), Square::from_name("d8"), None);
+    black.make(&mut game);
+    assert_eq!(8, game.halfmove_clock);
+    assert_eq!(21, game.fullmove_number);
+
+    let white = Move::new(&game, Square::from_name("e2"), Square::from_name("e3"), None);
+    white.make(&mut game);
+    assert_eq!(0, game.halfmove_clock);
+    assert_eq!(21, game.fullmove_number);
+
+    white.unmake(&mut game);
+    black.unmake(&mut game);
+    assert_eq!(fen, game.to_fen());
+}
+
+#[test]
+fn test_move_encoding() {
+    assert_eq!(2, std::mem::size_of::<Move>());
+
+    // every kind of move survives make and unmake, including the undo stack
+    let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    let mut game = Game::from_fen_str(fen);
+    let original = game.clone();
+    let moves = legal_moves(&game);
+    assert!(moves.iter().any(|m| m.is_castling()));
+    assert!(moves.iter().any(|m| m.is_capture()));
+    for m in moves.iter() {
+        let from = m.origin();
+        m.make(&mut game);
+        assert_eq!(1, game.undo.len());
+        assert!(game.board.get(from).is_none());
+        m.unmake(&mut game);
+        assert_eq!(original, game);
     }
+
+    let fen = "4k3/1P6/8/8/8/8/8/4K3 w - - 0 1";
+    let game = Game::from_fen_str(fen);
+    let mut promotions: Vec<String> = legal_moves(&game)
+        .into_iter()
+        .filter_map(|m| m.promotion().map(|_| m.to_string()))
+        .collect();
+    promotions.sort();
+    assert_eq!(vec!["b7b8b", "b7b8n", "b7b8q", "b7b8r"], promotions);
 }
 
-fn enumerate_rook(game: &Game, color: Color, from: Square, moves: &mut Vec<Move>) {
-    enumerate_straight_line(game, color, from, moves, (-1, 0));
-    enumerate_straight_line(game, color, from, moves, (1, 0));
-    enumerate_straight_line(game, color, from, moves, (0, -1));
-    enumerate_straight_line(game, color, from, moves, (0, 1));
+#[test]
+fn test_move_list() {
+    let mut list = MoveList::new();
+    assert!(list.is_empty());
+    for m in legal_moves(&Game::new()) {
+        list.push(m);
+    }
+    assert_eq!(20, list.len());
+    list.retain(|m| m.origin().rank() == RANK_2);
+    assert_eq!(16, list.len());
+    assert!(list.iter().all(|m| m.origin().rank() == RANK_2));
 }
 
-fn enumerate_bishop(game: &Game, color: Color, from: Square, moves: &mut Vec<Move>) {
-    enumerate_straight_line(game, color, from, moves, (-1, -1));
-    enumerate_straight_line(game, color, from, moves, (-1, 1));
-    enumerate_straight_line(game, color, from, moves, (1, -1));
-    enumerate_straight_line(game, color, from, moves, (1, 1));
+#[cfg(test)]
+fn perft_legal(game: &mut Game, depth: u32) -> u64 {
+    let moves = legal_moves(game);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut n = 0;
+    for m in moves {
+        m.make(game);
+        n += perft_legal(game, depth - 1);
+        m.unmake(game);
+    }
+    n
 }
 
-fn enumerate_knight(game: &Game, color: Color, from: Square, moves: &mut Vec<Move>) {
-    let offsets = [
-        (-2, -1),
-        (-2, 1),
-        (-1, 2),
-        (-1, -2),
-        (1, 2),
-        (1, -2),
-        (2, -1),
-        (2, 1),
+#[test]
+fn test_legal_moves_perft() {
+    let positions: [(&str, &[u64]); 5] = [
+        (
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8902],
+        ),
+        (
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        ),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]),
+        (
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        ),
+        (
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        ),
     ];
-
-    for off in offsets.iter() {
-        if let Some(sq) = from.offset(*off) {
-            if !game.board.contains_ally(sq, color) {
-                moves.push(Move::new(game, from, sq, None));
-            }
+    for (fen, counts) in positions.iter() {
+        let mut game = Game::from_fen_str(fen);
+        for (depth, count) in counts.iter().enumerate() {
+            assert_eq!(*count, perft_legal(&mut game, depth as u32 + 1), "{}", fen);
         }
     }
 }
+
+#[test]
+fn test_legal_moves_pins_and_checks() {
+    let legal = |fen: &str| {
+        let game = Game::from_fen_str(fen);
+        let mut moves: Vec<String> = legal_moves(&game).iter().map(|m| m.to_string()).collect();
+        moves.sort();
+        moves
+    };
+
+    // the rook is pinned on the file and can only move along it
+    assert_eq!(
+        vec![
+            "e1d1", "e1d2", "e1f1", "e1f2", "e2e3", "e2e4", "e2e5", "e2e6", "e2e7", "e2e8"
+        ],
+        legal("4r2k/8/8/8/8/8/4R3/4K3 w - - 0 1")
+    );
+
+    // double check, only the king can move
+    let moves = legal("4k3/8/8/8/1b6/8/4r3/R3K1N1 w - - 0 1");
+    assert!(moves.iter().all(|m| m.starts_with("e1")));
+    assert!(!moves.is_empty());
+
+    // single check by a knight, it must be captured or the king must move
+    let moves = legal("4k3/8/8/8/8/5n2/8/R3K2R w KQ - 0 1");
+    assert_eq!(vec!["e1d1", "e1e2", "e1f1", "e1f2"], moves);
+
+    // blocking a rook check, castling out of check is not allowed
+    let moves = legal("4r1k1/8/8/8/8/8/8/R3K1NR w KQ - 0 1");
+    assert!(moves.contains(&"g1e2".to_string()));
+    assert!(!moves.contains(&"e1g1".to_string()));
+    assert!(!moves.contains(&"a1a2".to_string()));
+
+    // the king may not retreat along the line of the checking rook
+    let moves = legal("4k3/8/8/8/8/8/8/r3K3 w - - 0 1");
+    assert!(!moves.contains(&"e1f1".to_string()));
+
+    // en passant would expose the king on the rank
+    let moves = legal("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1");
+    assert!(!moves.contains(&"e5d6".to_string()));
+    // en passant capturing a checking pawn
+    let moves = legal("8/8/8/2pP4/1K6/8/8/7k w - c6 0 1");
+    assert!(moves.contains(&"d5c6".to_string()));
+}
@@ -0,0 +1,406 @@
+use std::{fmt, time::Duration};
+
+use crate::{search::Selectivity, time::MOVE_OVERHEAD, tt};
+
+/// Engine settings that can be changed with `setoption`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub hash_mb: usize,
+    pub threads: usize,
+    pub multipv: usize,
+    pub move_overhead: Duration,
+    /// Centipawns a draw is worth less than an equal position for the engine
+    pub contempt: i32,
+    /// Only tells that the GUI may send `go ponder`
+    pub ponder: bool,
+    pub debug_log_file: Option<String>,
+    pub selectivity: Selectivity,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            hash_mb: tt::DEFAULT_SIZE_MB,
+            threads: 1,
+            multipv: 1,
+            move_overhead: MOVE_OVERHEAD,
+            contempt: 0,
+            ponder: false,
+            debug_log_file: None,
+            selectivity: Selectivity::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptionType {
+    Spin { default: i64, min: i64, max: i64 },
+    Check { default: bool },
+    String { default: &'static str },
+    Button,
+}
+
+/// Value given to `setoption`, checked against the type of the option
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptionValue<'a> {
+    Spin(i64),
+    Check(bool),
+    String(&'a str),
+    Button,
+}
+
+pub struct UciOption {
+    pub name: &'static str,
+    pub typ: OptionType,
+    apply: fn(&mut Options, OptionValue),
+}
+
+/// Value of string options that are not set, as the protocol has no empty strings
+const EMPTY: &str = "<empty>";
+
+/// All the options advertised on `uci`
+pub const OPTIONS: &[UciOption] = &[
+    UciOption {
+        name: "Hash",
+        typ: OptionType::Spin {
+            default: tt::DEFAULT_SIZE_MB as i64,
+            min: 1,
+            max: 4096,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.hash_mb = v as usize;
+            }
+        },
+    },
+    UciOption {
+        name: "Clear Hash",
+        typ: OptionType::Button,
+        apply: |_, _| {},
+    },
+    // the search is single threaded for now
+    UciOption {
+        name: "Threads",
+        typ: OptionType::Spin {
+            default: 1,
+            min: 1,
+            max: 1,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.threads = v as usize;
+            }
+        },
+    },
+    UciOption {
+        name: "MultiPV",
+        typ: OptionType::Spin {
+            default: 1,
+            min: 1,
+            max: 256,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.multipv = v as usize;
+            }
+        },
+    },
+    UciOption {
+        name: "Move Overhead",
+        typ: OptionType::Spin {
+            default: MOVE_OVERHEAD.as_millis() as i64,
+            min: 0,
+            max: 5000,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.move_overhead = Duration::from_millis(v as u64);
+            }
+        },
+    },
+    UciOption {
+        name: "Contempt",
+        typ: OptionType::Spin {
+            default: 0,
+            min: -100,
+            max: 100,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.contempt = v as i32;
+            }
+        },
+    },
+    UciOption {
+        name: "Ponder",
+        typ: OptionType::Check { default: false },
+        apply: |options, value| {
+            if let OptionValue::Check(v) = value {
+                options.ponder = v;
+            }
+        },
+    },
+    UciOption {
+        name: "Debug Log File",
+        typ: OptionType::String { default: EMPTY },
+        apply: |options, value| {
+            if let OptionValue::String(v) = value {
+                options.debug_log_file = match v {
+                    "" | EMPTY => None,
+                    path => Some(path.to_string()),
+                };
+            }
+        },
+    },
+    // tuning of the search, see `Selectivity`
+    UciOption {
+        name: "Null Move",
+        typ: OptionType::Check { default: true },
+        apply: |options, value| {
+            if let OptionValue::Check(v) = value {
+                options.selectivity.null_move = v;
+            }
+        },
+    },
+    UciOption {
+        name: "Null Move Reduction",
+        typ: OptionType::Spin {
+            default: 3,
+            min: 1,
+            max: 6,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.selectivity.null_move_reduction = v as u32;
+            }
+        },
+    },
+    UciOption {
+        name: "Late Move Reductions",
+        typ: OptionType::Check { default: true },
+        apply: |options, value| {
+            if let OptionValue::Check(v) = value {
+                options.selectivity.lmr = v;
+            }
+        },
+    },
+    UciOption {
+        name: "LMR Base",
+        typ: OptionType::Spin {
+            default: 75,
+            min: 0,
+            max: 300,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.selectivity.lmr_base = v as u32;
+            }
+        },
+    },
+    UciOption {
+        name: "LMR Divisor",
+        typ: OptionType::Spin {
+            default: 225,
+            min: 100,
+            max: 1000,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.selectivity.lmr_divisor = v as u32;
+            }
+        },
+    },
+    UciOption {
+        name: "Futility Pruning",
+        typ: OptionType::Check { default: true },
+        apply: |options, value| {
+            if let OptionValue::Check(v) = value {
+                options.selectivity.futility = v;
+            }
+        },
+    },
+    UciOption {
+        name: "Futility Margin",
+        typ: OptionType::Spin {
+            default: 100,
+            min: 0,
+            max: 1000,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.selectivity.futility_margin = v as i32;
+            }
+        },
+    },
+    UciOption {
+        name: "Razor Margin",
+        typ: OptionType::Spin {
+            default: 300,
+            min: 0,
+            max: 2000,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.selectivity.razor_margin = v as i32;
+            }
+        },
+    },
+    UciOption {
+        name: "Late Move Pruning",
+        typ: OptionType::Check { default: true },
+        apply: |options, value| {
+            if let OptionValue::Check(v) = value {
+                options.selectivity.lmp = v;
+            }
+        },
+    },
+    UciOption {
+        name: "LMP Base",
+        typ: OptionType::Spin {
+            default: 3,
+            min: 0,
+            max: 64,
+        },
+        apply: |options, value| {
+            if let OptionValue::Spin(v) = value {
+                options.selectivity.lmp_base = v as u32;
+            }
+        },
+    },
+    UciOption {
+        name: "Extensions",
+        typ: OptionType::Check { default: true },
+        apply: |options, value| {
+            if let OptionValue::Check(v) = value {
+                options.selectivity.extensions = v;
+            }
+        },
+    },
+    UciOption {
+        name: "Delta Pruning",
+        typ: OptionType::Check { default: true },
+        apply: |options, value| {
+            if let OptionValue::Check(v) = value {
+                options.selectivity.delta_pruning = v;
+            }
+        },
+    },
+];
+
+impl fmt::Display for UciOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+        match self.typ {
+            OptionType::Spin { default, min, max } => {
+                write!(f, "spin default {} min {} max {}", default, min, max)
+            }
+            OptionType::Check { default } => write!(f, "check default {}", default),
+            OptionType::String { default } => write!(f, "string default {}", default),
+            OptionType::Button => write!(f, "button"),
+        }
+    }
+}
+
+impl Options {
+    /// Apply `setoption name <name> value <value>`, the name is case
+    /// insensitive. Returns the option that was changed, or why nothing was
+    /// changed if the option does not exist or the value is invalid.
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> Result<&'static UciOption, String> {
+        let option = OPTIONS
+            .iter()
+            .find(|o| o.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown option {}", name))?;
+
+        let value = match option.typ {
+            OptionType::Spin { min, max, .. } => {
+                match value.and_then(|v| v.parse().ok()) {
+                    Some(v) if v >= min && v <= max => OptionValue::Spin(v),
+                    _ => {
+                        return Err(format!(
+                            "{} must be an integer between {} and {}",
+                            option.name, min, max
+                        ))
+                    }
+                }
+            }
+            OptionType::Check { .. } => match value.and_then(|v| v.parse().ok()) {
+                Some(v) => OptionValue::Check(v),
+                None => return Err(format!("{} must be true or false", option.name)),
+            },
+            OptionType::String { .. } => OptionValue::String(value.unwrap_or("")),
+            OptionType::Button => OptionValue::Button,
+        };
+        (option.apply)(self, value);
+
+        Ok(option)
+    }
+}
+
+#[test]
+fn test_set_option() {
+    let mut options = Options::default();
+
+    assert_eq!("Hash", options.set("hash", Some("64")).unwrap().name);
+    assert_eq!(64, options.hash_mb);
+    assert_eq!(
+        Err("Hash must be an integer between 1 and 4096".to_string()),
+        options.set("Hash", Some("0")).map(|o| o.name)
+    );
+    assert!(options.set("Hash", Some("big")).is_err());
+    assert!(options.set("Hash", None).is_err());
+    assert_eq!(64, options.hash_mb);
+
+    options.set("Move Overhead", Some("100")).unwrap();
+    assert_eq!(Duration::from_millis(100), options.move_overhead);
+    options.set("Contempt", Some("-10")).unwrap();
+    assert_eq!(-10, options.contempt);
+    options.set("Ponder", Some("true")).unwrap();
+    assert!(options.ponder);
+
+    options.set("Debug Log File", Some("/tmp/chess.log")).unwrap();
+    assert_eq!(Some("/tmp/chess.log".to_string()), options.debug_log_file);
+    options.set("Debug Log File", Some(EMPTY)).unwrap();
+    assert_eq!(None, options.debug_log_file);
+
+    options.set("null move", Some("false")).unwrap();
+    assert!(!options.selectivity.null_move);
+    options.set("LMR Divisor", Some("300")).unwrap();
+    assert_eq!(300, options.selectivity.lmr_divisor);
+    options.set("Extensions", Some("false")).unwrap();
+    assert!(!options.selectivity.extensions);
+    options.set("Delta Pruning", Some("false")).unwrap();
+    assert!(!options.selectivity.delta_pruning);
+
+    assert!(options.set("Clear Hash", None).is_ok());
+    assert_eq!(
+        Err("Null Move must be true or false".to_string()),
+        options.set("Null Move", Some("yes")).map(|o| o.name)
+    );
+    assert!(!options.selectivity.null_move);
+    assert_eq!(
+        Err("unknown option Unknown".to_string()),
+        options.set("Unknown", Some("1")).map(|o| o.name)
+    );
+}
+
+#[test]
+fn test_option_lines() {
+    let lines: Vec<String> = OPTIONS.iter().map(|o| o.to_string()).collect();
+    assert!(lines.contains(&"option name Hash type spin default 16 min 1 max 4096".to_string()));
+    assert!(lines.contains(&"option name Clear Hash type button".to_string()));
+    assert!(lines.contains(&"option name Ponder type check default false".to_string()));
+    assert!(lines.contains(&"option name Debug Log File type string default <empty>".to_string()));
+
+    // the defaults of the registry are the defaults of `Options`
+    let mut options = Options::default();
+    for o in OPTIONS {
+        let default = match o.typ {
+            OptionType::Spin { default, .. } => Some(default.to_string()),
+            OptionType::Check { default } => Some(default.to_string()),
+            OptionType::String { default } => Some(default.to_string()),
+            OptionType::Button => None,
+        };
+        options.set(o.name, default.as_deref()).unwrap();
+    }
+    assert_eq!(Options::default(), options);
+}
@@ -0,0 +1,165 @@
+use std::mem::size_of;
+
+use crate::{
+    game::Game,
+    moves::{legal_moves, Move},
+};
+
+/// Number of leaf nodes below each legal move of the position
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Divide {
+    pub moves: Vec<(Move, u64)>,
+}
+
+impl Divide {
+    pub fn nodes(&self) -> u64 {
+        self.moves.iter().map(|(_, n)| n).sum()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    key: u64,
+    depth: u32,
+    nodes: u64,
+}
+
+/// Node counts of the positions already visited, indexed by their Zobrist key.
+/// Transpositions are frequent in perft so this saves most of the work at
+/// higher depths.
+pub struct PerftTable {
+    entries: Vec<Option<Entry>>,
+    mask: usize,
+}
+
+impl PerftTable {
+    pub fn new(size_mb: usize) -> Self {
+        let max_entries = (size_mb * 1024 * 1024 / size_of::<Option<Entry>>()).max(1);
+        let len = 1 << (usize::BITS - 1 - max_entries.leading_zeros());
+
+        PerftTable {
+            entries: vec![None; len],
+            mask: len - 1,
+        }
+    }
+
+    fn probe(&self, key: u64, depth: u32) -> Option<u64> {
+        match self.entries[key as usize & self.mask] {
+            Some(e) if e.key == key && e.depth == depth => Some(e.nodes),
+            _ => None,
+        }
+    }
+
+    fn store(&mut self, key: u64, depth: u32, nodes: u64) {
+        self.entries[key as usize & self.mask] = Some(Entry { key, depth, nodes });
+    }
+}
+
+/// Number of leaf nodes of the tree of legal moves of `depth` plies. The moves
+/// of the last ply are counted without being played.
+pub fn perft(game: &mut Game, depth: u32, mut table: Option<&mut PerftTable>) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = legal_moves(game);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    if let Some(nodes) = table.as_ref().and_then(|t| t.probe(game.hash, depth)) {
+        return nodes;
+    }
+
+    let mut nodes = 0;
+    for m in moves.iter() {
+        m.make(game);
+        nodes += perft(game, depth - 1, table.as_deref_mut());
+        m.unmake(game);
+    }
+
+    if let Some(t) = table {
+        t.store(game.hash, depth, nodes);
+    }
+    nodes
+}
+
+/// Perft of each legal move, in the order of the move generator
+pub fn divide(game: &mut Game, depth: u32, mut table: Option<&mut PerftTable>) -> Divide {
+    let mut moves = Vec::new();
+    if depth == 0 {
+        return Divide { moves };
+    }
+
+    for m in legal_moves(game) {
+        m.make(game);
+        moves.push((m, perft(game, depth - 1, table.as_deref_mut())));
+        m.unmake(game);
+    }
+    Divide { moves }
+}
+
+/// Standard positions with their node counts for the first depths, see
+/// https://www.chessprogramming.org/Perft_Results
+pub const REFERENCE_POSITIONS: &[(&str, &[u64])] = &[
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        &[20, 400, 8902, 197281, 4865609],
+    ),
+    (
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        &[48, 2039, 97862, 4085603],
+    ),
+    (
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        &[14, 191, 2812, 43238, 674624],
+    ),
+    (
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        &[6, 264, 9467, 422333],
+    ),
+    (
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        &[44, 1486, 62379, 2103487],
+    ),
+    (
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        &[46, 2079, 89890, 3894594],
+    ),
+];
+
+#[test]
+fn test_reference_positions() {
+    // the deepest counts are left to the release build of the CLI
+    for (fen, counts) in REFERENCE_POSITIONS.iter() {
+        let mut game = Game::from_fen_str(fen);
+        for (depth, count) in counts.iter().enumerate().take(3) {
+            assert_eq!(*count, perft(&mut game, depth as u32 + 1, None), "{}", fen);
+        }
+    }
+}
+
+#[test]
+fn test_perft_hash_table() {
+    let mut table = PerftTable::new(1);
+    for (fen, counts) in REFERENCE_POSITIONS.iter() {
+        let mut game = Game::from_fen_str(fen);
+        assert_eq!(counts[3], perft(&mut game, 4, Some(&mut table)), "{}", fen);
+    }
+}
+
+#[test]
+fn test_divide() {
+    let mut game = Game::from_fen_str(REFERENCE_POSITIONS[1].0);
+    let original = game.clone();
+    let result = divide(&mut game, 3, None);
+    assert_eq!(original, game);
+    assert_eq!(48, result.moves.len());
+    assert_eq!(97862, result.nodes());
+
+    let castling = result.moves.iter().find(|(m, _)| m.to_string() == "e1g1");
+    assert_eq!(2059, castling.unwrap().1);
+
+    assert_eq!(1, perft(&mut game, 0, None));
+    assert!(divide(&mut game, 0, None).moves.is_empty());
+}
@@ -1,164 +1,1363 @@
-use std::cmp::{max, min};
+use crate::{
+    board::{Board, Color, Piece, PieceType},
+    eval::{evaluate, piece_value},
+    game::Game,
+    movepick::{is_quiet, History, MovePicker},
+    moves::{enumerate_moves, is_in_check, Move, MoveList},
+    see::see_ge,
+    square::Square,
+    tt::{Bound, TranspositionTable},
+};
 
-use crate::{board::{Color, Piece, PieceType}, eval::evaluate, game::Game, moves::Move, moves::enumerate_moves, square::Square};
+/// Score of a checkmate on the board, a mate found `n` plies from the root
+/// is scored `MATE_SCORE - n`
+pub const MATE_SCORE: i32 = 1_000_000;
+/// Scores within this many points of `MATE_SCORE` are mate scores
+const MAX_MATE_PLY: i32 = 1000;
+const INFINITY: i32 = 10_000_000;
+/// Captures that cannot bring the score within this margin of alpha are skipped
+const DELTA_MARGIN: i32 = 200;
+/// Maximum depth of the main search, also the size of the PV table
+pub const MAX_PLY: usize = 64;
+/// Number of nodes between two calls to the stop condition
+const NODES_BETWEEN_STOP_CHECKS: u64 = 1024;
+/// Maximum remaining depth of the reverse futility pruning
+const FUTILITY_DEPTH: u32 = 6;
+/// Maximum remaining depth of the razoring
+const RAZOR_DEPTH: u32 = 3;
+/// Maximum remaining depth of the late move pruning
+const LMP_DEPTH: u32 = 3;
+/// First iteration searched with an aspiration window around the score of
+/// the previous one
+const ASPIRATION_DEPTH: u32 = 4;
+/// Initial half width of the aspiration window, doubled on each failure
+const ASPIRATION_WINDOW: i32 = 25;
+/// Half width above which the window is opened on the failing side
+const MAX_ASPIRATION_WINDOW: i32 = 1000;
+/// Minimum remaining depth to look for a singular move
+const SINGULAR_DEPTH: u32 = 6;
+/// Margin per remaining ply below the transposition table score that the
+/// other moves must stay under for the hash move to be singular
+const SINGULAR_MARGIN: i32 = 3;
 
-pub fn best_move(game: &Game, depth: u32) -> Option<(Move, i32)> {
-    if depth == 0 {
-        return None;
-    }
+/// Selective search techniques, each one can be disabled and tuned. Except
+/// for the late move reductions and the extensions they only apply to the
+/// nodes searched with a null window, where the exact score is not needed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Selectivity {
+    /// Give the opponent a free move, if the reduced search still fails high
+    /// the position is good enough to cut
+    pub null_move: bool,
+    /// Depth reduction of the null move search, on top of one ply every six
+    pub null_move_reduction: u32,
+    /// Search the quiet moves ordered last with a reduced depth
+    pub lmr: bool,
+    /// The reduction is `lmr_base / 100 + ln(depth) * ln(move number) * 100 / lmr_divisor`
+    pub lmr_base: u32,
+    pub lmr_divisor: u32,
+    /// Reverse futility pruning and razoring near the leaves
+    pub futility: bool,
+    /// Margin per remaining ply above beta to cut without searching
+    pub futility_margin: i32,
+    /// Margin per remaining ply below alpha to only search captures
+    pub razor_margin: i32,
+    /// Skip the quiet moves ordered last near the leaves
+    pub lmp: bool,
+    /// Number of quiet moves searched is `lmp_base + depth * depth`
+    pub lmp_base: u32,
+    /// Search one ply deeper the checks, the recaptures, the passed pawn
+    /// pushes and the hash move when it is much better than the others
+    pub extensions: bool,
+    /// Skip in the quiescence search the captures that cannot bring the score
+    /// back to alpha even when winning the captured piece for free
+    pub delta_pruning: bool,
+}
 
-    let mut game = game.clone();
+impl Default for Selectivity {
+    fn default() -> Self {
+        Selectivity {
+            null_move: true,
+            null_move_reduction: 3,
+            lmr: true,
+            lmr_base: 75,
+            lmr_divisor: 225,
+            futility: true,
+            futility_margin: 100,
+            razor_margin: 300,
+            lmp: true,
+            lmp_base: 3,
+            extensions: true,
+            delta_pruning: true,
+        }
+    }
+}
 
-    let moves = enumerate_moves(&mut game);
+impl Selectivity {
+    /// Every move searched to exactly the full depth
+    pub fn none() -> Self {
+        Selectivity {
+            null_move: false,
+            lmr: false,
+            futility: false,
+            lmp: false,
+            extensions: false,
+            delta_pruning: false,
+            ..Default::default()
+        }
+    }
 
-    let (mut best_score, best_m) = minmax(&mut game, 0, depth, &moves);
-    best_score *= game.player.to_int(); 
+    fn lmr_reduction(&self, remaining: u32, move_number: u32) -> u32 {
+        let log = (remaining as f64).ln() * (move_number as f64).ln();
+        (self.lmr_base as f64 / 100.0 + log * 100.0 / self.lmr_divisor as f64) as u32
+    }
+}
 
-    Some((best_m?, best_score))
+/// Result of a completed iteration of the iterative deepening
+#[derive(Clone, Debug)]
+pub struct Iteration {
+    pub depth: u32,
+    /// Maximum ply reached, including the quiescence search
+    pub seldepth: u32,
+    /// From the point of view of the player to move
+    pub score: i32,
+    /// `Exact` unless the score is only a bound of the real score
+    pub bound: Bound,
+    /// Principal variation, the first move is the best move
+    pub pv: Vec<Move>,
+    /// Number of nodes searched since the start of the search
+    pub nodes: u64,
+    /// Rank of the line when searching several lines, starting at 1
+    pub multipv: usize,
+    /// Permille of the transposition table used by this search
+    pub hashfull: u32,
 }
 
-fn contains_king_capture(moves: &Vec<Move>) -> bool {
-    moves.iter().any(|m| match m {
-        Move::Normal {
-            capture:
-                Some(Piece {
-                    typ: PieceType::King,
-                    ..
-                }),
-            ..
-        } => true,
-        _ => false,
-    })
+impl Iteration {
+    pub fn best_move(&self) -> Move {
+        self.pv[0]
+    }
 }
 
-fn square_is_threatened(square: Square, moves: &Vec<Move>) -> bool {
-    moves.iter().any(|m| match m {
-        Move::Normal {
-            to: sq, ..
-        } if *sq == square => true,
-        _ => false,
-    })
+/// Called with the depth, the move and its number for each root move
+type RootMoveCallback<'a> = Box<dyn FnMut(u32, Move, usize) + 'a>;
+
+pub struct Search<'a> {
+    tt: &'a mut TranspositionTable,
+    /// Triangular PV table, `pv_table[ply]` holds the PV found from `ply`
+    pv_table: Vec<[Option<Move>; MAX_PLY]>,
+    pv_length: [usize; MAX_PLY],
+    /// PV of the previous iteration, searched first
+    previous_pv: Vec<Move>,
+    following_pv: bool,
+    /// Depth of the current iteration
+    depth: u32,
+    seldepth: u32,
+    nodes: u64,
+    stop_condition: Option<Box<dyn FnMut(u64) -> bool + 'a>>,
+    /// Root moves to consider, all moves if empty
+    search_moves: Vec<Move>,
+    root_move_callback: Option<RootMoveCallback<'a>>,
+    /// Number of root moves to find a principal variation for
+    multipv: usize,
+    /// Score of a draw for the player to move at the root, a positive contempt
+    /// makes the engine avoid draws
+    contempt: i32,
+    root_player: Color,
+    /// Set when the stop condition fired, the current iteration is then discarded
+    stopped: bool,
+    history: History,
+    /// Move played at each ply of the current line and the piece it moved
+    played: [Option<(Move, Piece)>; MAX_PLY],
+    /// Move left out of the search at each ply, to check if it is singular
+    excluded: [Option<Move>; MAX_PLY],
+    /// Disabled in tests to measure what the move ordering saves
+    #[cfg(test)]
+    ordering: bool,
+    selectivity: Selectivity,
 }
 
-fn minmax(game: &mut Game, depth: u32, max_depth: u32, moves: &Vec<Move>) -> (i32, Option<Move>) {
-    if depth == max_depth {
-        (evaluate(&game.board, depth), None)
-    } else {
-        // TODO: remove the branches
+impl<'a> Search<'a> {
+    pub fn new(tt: &'a mut TranspositionTable) -> Self {
+        Search {
+            tt,
+            pv_table: vec![[None; MAX_PLY]; MAX_PLY],
+            pv_length: [0; MAX_PLY],
+            previous_pv: Vec::new(),
+            following_pv: false,
+            depth: 0,
+            seldepth: 0,
+            nodes: 0,
+            stop_condition: None,
+            search_moves: Vec::new(),
+            root_move_callback: None,
+            multipv: 1,
+            contempt: 0,
+            root_player: Color::White,
+            stopped: false,
+            history: History::new(),
+            played: [None; MAX_PLY],
+            excluded: [None; MAX_PLY],
+            #[cfg(test)]
+            ordering: true,
+            selectivity: Selectivity::default(),
+        }
+    }
+
+    /// The condition is called with the number of searched nodes every few
+    /// nodes, returning true aborts the current iteration. The first iteration
+    /// is never aborted so that there is always a move to play.
+    pub fn set_stop_condition(&mut self, condition: impl FnMut(u64) -> bool + 'a) {
+        self.stop_condition = Some(Box::new(condition));
+    }
+
+    /// Only search these moves at the root, as in `go searchmoves`
+    pub fn set_search_moves(&mut self, moves: Vec<Move>) {
+        self.search_moves = moves;
+    }
+
+    /// The callback is called with the depth of the iteration, the move and its
+    /// number (starting at 1) before searching each legal root move
+    pub fn set_root_move_callback(&mut self, callback: impl FnMut(u32, Move, usize) + 'a) {
+        self.root_move_callback = Some(Box::new(callback));
+    }
+
+    /// Search the best `multipv` lines instead of only the best one
+    pub fn set_multipv(&mut self, multipv: usize) {
+        self.multipv = multipv.max(1);
+    }
+
+    pub fn set_contempt(&mut self, contempt: i32) {
+        self.contempt = contempt;
+    }
+
+    pub fn set_selectivity(&mut self, selectivity: Selectivity) {
+        self.selectivity = selectivity;
+    }
+
+    /// Search at depth 1, 2, ... up to `max_depth`. `on_iteration` is called
+    /// for each line of each completed iteration and can return false to stop
+    /// the search. It is also called with a `Lower` or `Upper` bound each time
+    /// the search of a line fails outside of its aspiration window, its return
+    /// value is then ignored. Returns the best line of the last completed
+    /// iteration, or `None` if there is no legal move.
+    pub fn iterative_deepening(
+        &mut self,
+        game: &Game,
+        max_depth: u32,
+        mut on_iteration: impl FnMut(&Iteration) -> bool,
+    ) -> Option<Iteration> {
+        let mut game = game.clone();
+        // the last ply of the PV table is left to the extensions
+        let max_depth = max_depth.min(MAX_PLY as u32 - 1);
+
+        self.tt.new_search();
+        self.nodes = 0;
+        self.stopped = false;
+        self.previous_pv.clear();
+        self.root_player = game.player;
+
+        let mut result = None;
+        let mut previous_lines: Vec<Iteration> = Vec::new();
+
+        'iterations: for depth in 1..=max_depth {
+            self.depth = depth;
+            self.seldepth = 0;
+
+            // each line is searched without the first moves of the previous ones
+            let mut lines: Vec<Iteration> = Vec::new();
+            for multipv in 1..=self.multipv {
+                let mut moves = enumerate_moves(&game);
+                if !self.search_moves.is_empty() {
+                    moves.retain(|m| self.search_moves.contains(m));
+                }
+                moves.retain(|m| lines.iter().all(|l| l.best_move() != *m));
+
+                // aspiration window around the score of the previous iteration,
+                // widened until the score falls inside of it
+                let previous = previous_lines.get(multipv - 1);
+                let mut delta = ASPIRATION_WINDOW;
+                let (mut alpha, mut beta) = match previous {
+                    Some(line) if depth >= ASPIRATION_DEPTH && mate_in(line.score).is_none() => {
+                        (line.score - delta, line.score + delta)
+                    }
+                    _ => (-INFINITY, INFINITY),
+                };
+
+                let score = loop {
+                    self.following_pv = multipv == 1;
+                    let (score, _) = self.alphabeta(&mut game, 0, depth, alpha, beta, &moves);
+                    if self.stopped {
+                        break 'iterations;
+                    }
+
+                    let bound = if score <= alpha && alpha > -INFINITY {
+                        delta *= 2;
+                        alpha = if delta > MAX_ASPIRATION_WINDOW || mate_in(score).is_some() {
+                            -INFINITY
+                        } else {
+                            score - delta
+                        };
+                        Bound::Upper
+                    } else if score >= beta && beta < INFINITY {
+                        delta *= 2;
+                        beta = if delta > MAX_ASPIRATION_WINDOW || mate_in(score).is_some() {
+                            INFINITY
+                        } else {
+                            score + delta
+                        };
+                        Bound::Lower
+                    } else {
+                        break score;
+                    };
+
+                    // no root move raised alpha after a fail low, the previous
+                    // PV is still the best guess
+                    let pv = match bound {
+                        Bound::Lower => self.root_pv(),
+                        _ => previous.map(|line| line.pv.clone()).unwrap_or_default(),
+                    };
+                    if !pv.is_empty() {
+                        on_iteration(&Iteration {
+                            depth,
+                            seldepth: self.seldepth,
+                            score,
+                            bound,
+                            pv,
+                            nodes: self.nodes,
+                            multipv,
+                            hashfull: self.tt.hashfull(),
+                        });
+                    }
+                };
+
+                if self.pv_length[0] == 0 {
+                    break;
+                }
+
+                let pv = self.root_pv();
+                if multipv == 1 {
+                    self.previous_pv = pv.clone();
+                }
+
+                lines.push(Iteration {
+                    depth,
+                    seldepth: self.seldepth,
+                    score,
+                    bound: Bound::Exact,
+                    pv,
+                    nodes: self.nodes,
+                    multipv,
+                    hashfull: self.tt.hashfull(),
+                });
+            }
+
+            if lines.is_empty() {
+                break;
+            }
+
+            let mut go_on = true;
+            for line in lines.iter() {
+                go_on &= on_iteration(line);
+            }
+            result = lines.first().cloned();
+            previous_lines = lines;
 
-        let mut best_score = match game.player {
-            Color::White => -1000000,
-            Color::Black => 1000000,
+            if !go_on {
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Score of a draw from the point of view of the player to move
+    fn draw_score(&self, game: &Game) -> i32 {
+        if game.player == self.root_player {
+            -self.contempt
+        } else {
+            self.contempt
+        }
+    }
+
+    /// Count the node and poll the stop condition
+    fn visit_node(&mut self) {
+        self.nodes += 1;
+
+        if self.depth > 1 && self.nodes.is_multiple_of(NODES_BETWEEN_STOP_CHECKS) {
+            if let Some(condition) = self.stop_condition.as_mut() {
+                if condition(self.nodes) {
+                    self.stopped = true;
+                }
+            }
+        }
+    }
+
+    /// PV found by the last search of the root
+    fn root_pv(&self) -> Vec<Move> {
+        self.pv_table[0][..self.pv_length[0]]
+            .iter()
+            .map(|m| m.unwrap())
+            .collect()
+    }
+
+    /// Record `m` followed by the PV of the child as the PV at `ply`
+    fn update_pv(&mut self, ply: usize, m: Move) {
+        let (current, children) = self.pv_table.split_at_mut(ply + 1);
+        let child_length = if ply + 1 < MAX_PLY {
+            self.pv_length[ply + 1]
+        } else {
+            0
         };
+
+        current[ply][0] = Some(m);
+        current[ply][1..=child_length].copy_from_slice(&children[0][..child_length]);
+        self.pv_length[ply] = child_length + 1;
+    }
+
+    /// Negamax alpha-beta search, the score is from the point of view of the player
+    /// to move and may lie outside of the `[alpha, beta]` window (fail-soft)
+    fn alphabeta(
+        &mut self,
+        game: &mut Game,
+        depth: u32,
+        max_depth: u32,
+        mut alpha: i32,
+        mut beta: i32,
+        moves: &[Move],
+    ) -> (i32, Option<Move>) {
+        let ply = depth as usize;
+        self.pv_length[ply] = 0;
+        let excluded = self.excluded[ply];
+
+        self.visit_node();
+        if self.stopped {
+            return (0, None);
+        }
+
+        // a checkmate delivered on the last move takes precedence over the fifty-move rule
+        if depth > 0 && game.is_fifty_move_draw() && has_legal_move(game, moves) {
+            return (self.draw_score(game), None);
+        }
+
+        // a repetition inside the search tree is scored as a draw since it can be
+        // repeated again, positions from before the root must have occurred twice
+        if depth > 0
+            && (game.repetitions_within(depth as usize) > 0 || game.is_threefold_repetition())
+        {
+            return (self.draw_score(game), None);
+        }
+
+        // mate distance pruning: even mating on the next move cannot beat a
+        // shorter mate found closer to the root
+        if depth > 0 {
+            alpha = alpha.max(-MATE_SCORE + ply as i32);
+            beta = beta.min(MATE_SCORE - ply as i32 - 1);
+            if alpha >= beta {
+                return (alpha, None);
+            }
+        }
+
+        if depth == max_depth {
+            return (self.quiescence(game, depth, alpha, beta), None);
+        }
+
+        // the entry of this position holds the result with the excluded move
+        let tt_entry = if excluded.is_none() {
+            self.tt.probe(game.hash).copied()
+        } else {
+            None
+        };
+        let tt_move = match tt_entry {
+            // the root needs a move from the current list so it is always searched
+            Some(e) if depth > 0 && e.depth >= max_depth - depth => {
+                let score = score_from_tt(e.score, ply);
+                match e.bound {
+                    Bound::Exact => return (score, e.best_move),
+                    Bound::Lower if score >= beta => return (score, e.best_move),
+                    Bound::Upper if score <= alpha => return (score, e.best_move),
+                    _ => e.best_move,
+                }
+            }
+            Some(e) => e.best_move,
+            None => None,
+        };
+
+        let remaining = max_depth - depth;
+        let in_check = is_in_check(&game.board, game.player);
+        let pv_node = beta - alpha > 1;
+        let sel = self.selectivity;
+        let prunable = depth > 0
+            && !pv_node
+            && !in_check
+            && excluded.is_none()
+            && mate_in(beta).is_none();
+        let static_eval = evaluate(&game.board) * game.player.to_int();
+
+        if prunable && sel.futility {
+            // reverse futility: even a bad quiet move would keep the score above beta
+            if remaining <= FUTILITY_DEPTH
+                && static_eval - sel.futility_margin * remaining as i32 >= beta
+            {
+                return (static_eval, None);
+            }
+            // razoring: so far below alpha that only captures can save the position
+            if remaining <= RAZOR_DEPTH && static_eval + sel.razor_margin * remaining as i32 <= alpha
+            {
+                let score = self.quiescence(game, depth, alpha, alpha + 1);
+                if score <= alpha {
+                    return (score, None);
+                }
+            }
+        }
+
+        // no null move right after another one, nor when only pawns are left
+        // since passing is then often the best move (zugzwang)
+        if prunable
+            && sel.null_move
+            && remaining >= 2
+            && static_eval >= beta
+            && self.played[ply - 1].is_some()
+            && has_non_pawn_material(&game.board, game.player)
+        {
+            let reduction = sel.null_move_reduction + remaining / 6;
+            game.make_null_move();
+            self.played[ply] = None;
+            let opp_moves = enumerate_moves(game);
+            let child_max_depth = max_depth.saturating_sub(reduction).max(depth + 1);
+            let (score, _) =
+                self.alphabeta(game, depth + 1, child_max_depth, -beta, -beta + 1, &opp_moves);
+            game.unmake_null_move();
+
+            if self.stopped {
+                return (0, None);
+            }
+            let score = -score;
+            if score >= beta {
+                // a mate found after passing is not proven
+                return (if mate_in(score).is_some() { beta } else { score }, None);
+            }
+        }
+
+        // singular extension: the hash move is searched one ply deeper if a
+        // reduced search of the other moves fails low against a margin below
+        // its score, since the line then depends on this move only
+        let mut singular_move = None;
+        if let Some(e) = tt_entry {
+            if sel.extensions
+                && depth > 0
+                && remaining >= SINGULAR_DEPTH
+                && e.bound != Bound::Upper
+                && e.depth + 3 >= remaining
+                && mate_in(e.score).is_none()
+                && tt_move.is_some_and(|m| moves.contains(&m))
+            {
+                let singular_beta =
+                    score_from_tt(e.score, ply) - SINGULAR_MARGIN * remaining as i32;
+                let following_pv = self.following_pv;
+                self.following_pv = false;
+                self.excluded[ply] = tt_move;
+                let (score, _) = self.alphabeta(
+                    game,
+                    depth,
+                    depth + remaining / 2,
+                    singular_beta - 1,
+                    singular_beta,
+                    moves,
+                );
+                self.excluded[ply] = None;
+                // the line found without the hash move is not the PV of this node
+                self.pv_length[ply] = 0;
+                self.following_pv = following_pv;
+
+                if self.stopped {
+                    return (0, None);
+                }
+                if score < singular_beta {
+                    singular_move = tt_move;
+                }
+            }
+        }
+
+        // search the move of the previous PV first, then the best move of a previous search
+        let pv_move = if self.following_pv {
+            self.previous_pv.get(ply).copied()
+        } else {
+            None
+        };
+        self.following_pv = pv_move.is_some_and(|pv_move| moves.contains(&pv_move));
+        let hash_move = if self.following_pv { pv_move } else { tt_move };
+
+        let previous = if ply > 0 { self.played[ply - 1] } else { None };
+        #[cfg(not(test))]
+        let picker = MovePicker::new(moves, game, hash_move, &self.history, ply, previous);
+        #[cfg(test)]
+        let picker = if self.ordering {
+            MovePicker::new(moves, game, hash_move, &self.history, ply, previous)
+        } else {
+            MovePicker::unordered(moves, hash_move)
+        };
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
         let mut best_m = None;
+        let mut legal_moves = 0;
+        // quiet moves that did not cause a cutoff, their history is lowered
+        let mut quiets_tried = MoveList::new();
 
-        'lop: for m in moves.iter() {
+        for m in picker {
+            if Some(m) == excluded {
+                continue;
+            }
+            let piece = game.board.get(m.origin()).unwrap();
             m.make(game);
 
-            let opp_moves = enumerate_moves(game);
+            // the move is pseudo-legal, skip it if it leaves our king in check
+            // (castling through check is already ruled out by enumerate_moves)
+            if is_in_check(&game.board, game.player.opposite()) {
+                m.unmake(game);
+                self.following_pv = false;
+                continue;
+            }
+            legal_moves += 1;
+            let gives_check = is_in_check(&game.board, game.player);
+            let quiet = is_quiet(&m) && !gives_check;
 
-            // if the opponent has a (pseudo-legal) king capture, it means that
-            // the current move is illegal so we continue
-            if contains_king_capture(&opp_moves) {
+            // late move pruning, as long as a move that avoids getting mated was found
+            if prunable
+                && sel.lmp
+                && quiet
+                && remaining <= LMP_DEPTH
+                && quiets_tried.len() as u32 >= sel.lmp_base + remaining * remaining
+                && mate_in(best_score).is_none_or(|n| n > 0)
+            {
                 m.unmake(game);
                 continue;
             }
 
-            if let Move::Castling { from, to, ..} = m {
-                assert_eq!(from.rank(), to.rank());
-                let min_file = min(from.file(), to.file());
-                let max_file = max(from.file(), to.file());
+            let opp_moves = enumerate_moves(game);
+            self.played[ply] = Some((m, piece));
 
-                for f in min_file..max_file {
-                    if square_is_threatened(Square::new_nocheck(f, from.rank()), &opp_moves) {
-                        m.unmake(game);
-                        continue 'lop;
-                    }
+            if depth == 0 {
+                if let Some(callback) = self.root_move_callback.as_mut() {
+                    callback(self.depth, m, legal_moves);
                 }
             }
 
-            let (score, _) = minmax(game, depth + 1, max_depth, &opp_moves);
+            // extensions, at most one ply per move and up to twice the depth of
+            // the iteration so that the search ends
+            let extend = sel.extensions
+                && depth < 2 * self.depth
+                && max_depth + 1 < MAX_PLY as u32
+                && (gives_check
+                    || Some(m) == singular_move
+                    || is_recapture(m, previous)
+                    || is_passed_pawn_push(&game.board, m, piece));
+            let max_depth = max_depth + extend as u32;
 
-            // we are now game.player.opposite() because m.make(game) changed the current player
-            match game.player.opposite() {
-                Color::White => {
-                    if score > best_score {
-                        best_score = score;
-                        best_m = Some(m);
-                    }
+            // late move reduction, the move is searched again at full depth if
+            // the reduced search does not fail low
+            let mut reduction = 0;
+            if sel.lmr && depth > 0 && remaining >= 3 && legal_moves > 1 && quiet && !in_check {
+                reduction = sel.lmr_reduction(remaining, legal_moves as u32);
+                if pv_node {
+                    reduction = reduction.saturating_sub(1);
                 }
-                Color::Black => {
-                    if score < best_score {
-                        best_score = score;
-                        best_m = Some(m);
-                    }
+                // at least one ply is left to the child
+                reduction = reduction.min(remaining - 2);
+            }
+
+            // principal variation search: the first move is expected to be the
+            // best one, the others are searched with a null window to prove that
+            // they are worse and only searched again if they are not
+            let mut score;
+            if legal_moves == 1 {
+                let (s, _) = self.alphabeta(game, depth + 1, max_depth, -beta, -alpha, &opp_moves);
+                score = -s;
+            } else {
+                let (s, _) = self.alphabeta(
+                    game,
+                    depth + 1,
+                    max_depth - reduction,
+                    -alpha - 1,
+                    -alpha,
+                    &opp_moves,
+                );
+                score = -s;
+                if score > alpha && reduction > 0 {
+                    let (s, _) =
+                        self.alphabeta(game, depth + 1, max_depth, -alpha - 1, -alpha, &opp_moves);
+                    score = -s;
                 }
-            };
+                if score > alpha && score < beta {
+                    let (s, _) =
+                        self.alphabeta(game, depth + 1, max_depth, -beta, -alpha, &opp_moves);
+                    score = -s;
+                }
+            }
+
             m.unmake(game);
+
+            // only the first move can be on the previous PV
+            self.following_pv = false;
+
+            if self.stopped {
+                return (0, None);
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_m = Some(m);
+
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, m);
+                }
+                if alpha >= beta {
+                    if is_quiet(&m) {
+                        self.history
+                            .update(game.player, ply, remaining, m, previous, &quiets_tried);
+                    }
+                    break;
+                }
+            }
+            if is_quiet(&m) {
+                quiets_tried.push(m);
+            }
+        }
+
+        // only the excluded move is legal, it is singular
+        if legal_moves == 0 && excluded.is_some() {
+            return (alpha, None);
         }
 
         // we have no legal move
         // this is either a checkmate or a stalemate
-        if best_m.is_none() {
-            game.player = game.player.opposite();
-            let opp_moves = enumerate_moves(game);
-            game.player = game.player.opposite();
-            if contains_king_capture(&opp_moves) {
-                // do nothing, the initial best_score is good
+        if legal_moves == 0 {
+            best_score = if is_in_check(&game.board, game.player) {
+                -MATE_SCORE + ply as i32
             } else {
-                best_score = 0;
-            }
+                self.draw_score(game)
+            };
+        }
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        if excluded.is_none() {
+            self.tt.store(
+                game.hash,
+                remaining,
+                score_to_tt(best_score, ply),
+                bound,
+                best_m,
+            );
         }
-        (best_score, best_m.copied())
+
+        (best_score, best_m)
     }
-}
 
-pub fn perft(depth: u32) -> u32 {
-    let mut game = Game::new();
+    /// Search captures and promotions until the position is quiet so that the
+    /// evaluation is not done while pieces are hanging. When in check all the
+    /// evasions are searched instead.
+    fn quiescence(&mut self, game: &mut Game, depth: u32, mut alpha: i32, beta: i32) -> i32 {
+        self.visit_node();
+        if self.stopped {
+            return 0;
+        }
+        // every leaf of the main search goes through the quiescence search
+        self.seldepth = self.seldepth.max(depth);
 
-    let moves = enumerate_moves(&game);
+        let stand_pat = evaluate(&game.board) * game.player.to_int();
 
-    perft_rec(&mut game, 0, depth, &moves)
-}
+        // the evasions are not limited to captures, a perpetual check would
+        // otherwise be searched forever
+        if depth as usize >= MAX_PLY {
+            return stand_pat;
+        }
+        if game.repetitions_within(depth as usize) > 0 {
+            return self.draw_score(game);
+        }
 
-fn perft_rec(game: &mut Game, depth: u32, max_depth: u32, moves: &Vec<Move>) -> u32 {
-    if depth == max_depth {
-        1
-    } else {
-        let mut n = 0;
+        let in_check = is_in_check(&game.board, game.player);
+        let mut best_score = -INFINITY;
 
-        'lop: for m in moves {
-            m.make(game);
+        if !in_check {
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            if stand_pat > alpha {
+                alpha = stand_pat;
+            }
+            best_score = stand_pat;
+        }
 
-            let opp_moves = enumerate_moves(game);
+        let mut moves = enumerate_moves(game);
+        if !in_check {
+            moves.retain(|m| m.is_capture() || m.promotion().is_some());
+        }
+        let picker = MovePicker::new(&moves, game, None, &self.history, depth as usize, None);
+
+        let mut legal_moves = 0;
+
+        for m in picker {
+            if !in_check {
+                if self.selectivity.delta_pruning && m.promotion().is_none() {
+                    let gain = m.captured(&game.board).map_or(0, piece_value);
+                    if stand_pat + gain + DELTA_MARGIN < alpha {
+                        continue;
+                    }
+                }
+                // captures that lose material are not worth searching
+                if !see_ge(game, m, 0) {
+                    continue;
+                }
+            }
+
+            m.make(game);
 
-            if contains_king_capture(&opp_moves) {
+            if is_in_check(&game.board, game.player.opposite()) {
                 m.unmake(game);
                 continue;
             }
+            legal_moves += 1;
 
-            if let Move::Castling { from, to, ..} = m {
-                assert_eq!(from.rank(), to.rank());
-                let min_file = min(from.file(), to.file());
-                let max_file = max(from.file(), to.file());
+            let score = -self.quiescence(game, depth + 1, -beta, -alpha);
 
-                for f in min_file..max_file {
-                    if square_is_threatened(Square::new_nocheck(f, from.rank()), &opp_moves) {
-                        m.unmake(game);
-                        continue 'lop;
-                    }
+            m.unmake(game);
+
+            if score > best_score {
+                best_score = score;
+
+                if score > alpha {
+                    alpha = score;
+                }
+                if alpha >= beta {
+                    break;
                 }
             }
+        }
 
-            let x = perft_rec(game, depth+1, max_depth, &opp_moves);
-            if depth == 0 {
-                println!("{}: {}", m.to_string(), x);
+        if in_check && legal_moves == 0 {
+            return -MATE_SCORE + depth as i32;
+        }
+
+        best_score
+    }
+}
+
+/// Returns the best move and its score from the point of view of the player to move
+pub fn best_move(game: &Game, depth: u32, tt: &mut TranspositionTable) -> Option<(Move, i32)> {
+    if depth == 0 {
+        return None;
+    }
+
+    let iteration = Search::new(tt).iterative_deepening(game, depth, |_| true)?;
+
+    Some((iteration.best_move(), iteration.score))
+}
+
+/// Number of moves until mate if `score` is a mate score, negative when the
+/// player to move is getting mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score > MATE_SCORE - MAX_MATE_PLY {
+        Some((MATE_SCORE - score + 1) / 2)
+    } else if score < -MATE_SCORE + MAX_MATE_PLY {
+        Some(-(MATE_SCORE + score) / 2)
+    } else {
+        None
+    }
+}
+
+/// Mate scores are stored relative to the position instead of the root since
+/// the same position can be reached at different plies
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    match mate_in(score) {
+        Some(n) if n > 0 => score + ply as i32,
+        Some(_) => score - ply as i32,
+        None => score,
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    match mate_in(score) {
+        Some(n) if n > 0 => score - ply as i32,
+        Some(_) => score + ply as i32,
+        None => score,
+    }
+}
+
+fn has_non_pawn_material(board: &Board, color: Color) -> bool {
+    [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight]
+        .iter()
+        .any(|typ| board.pieces(*typ, color) != 0)
+}
+
+/// `m` captures back the piece that captured on the `previous` move
+fn is_recapture(m: Move, previous: Option<(Move, Piece)>) -> bool {
+    m.is_capture()
+        && previous.is_some_and(|(p, _)| p.is_capture() && p.destination() == m.destination())
+}
+
+/// `m`, which has been made, moves `piece` as a pawn with no opposing pawn
+/// in front of it or on the adjacent files to the sixth or seventh rank
+fn is_passed_pawn_push(board: &Board, m: Move, piece: Piece) -> bool {
+    if piece.typ != PieceType::Pawn {
+        return false;
+    }
+    let to = m.destination();
+    let (ahead, relative_rank) = match piece.color {
+        Color::White => (to.rank() + 1..8, to.rank()),
+        Color::Black => (0..to.rank(), 7 - to.rank()),
+    };
+    if relative_rank < 5 {
+        return false;
+    }
+
+    let opposing_pawns = board.pieces(PieceType::Pawn, piece.color.opposite());
+    let first_file = to.file().saturating_sub(1);
+    let last_file = (to.file() + 1).min(7);
+    !ahead.into_iter().any(|rank| {
+        (first_file..=last_file)
+            .any(|file| opposing_pawns & Square::new_nocheck(file, rank).bitboard() != 0)
+    })
+}
+
+/// Returns true if one of the pseudo legal `moves` does not leave the king in check
+fn has_legal_move(game: &mut Game, moves: &[Move]) -> bool {
+    moves.iter().any(|m| {
+        m.make(game);
+        let legal = !is_in_check(&game.board, game.player.opposite());
+        m.unmake(game);
+        legal
+    })
+}
+
+#[test]
+fn test_fifty_move_rule() {
+    let fen = "7k/8/8/8/8/8/8/KQ6 w - - 0 80";
+    let game = Game::from_fen_str(fen);
+    let (_, score) = best_move(&game, 2, &mut TranspositionTable::new(1)).unwrap();
+    assert!(score > 500);
+
+    // every move reaches the hundredth halfmove without a capture or a pawn move
+    let fen = "7k/8/8/8/8/8/8/KQ6 w - - 99 80";
+    let game = Game::from_fen_str(fen);
+    let (_, score) = best_move(&game, 2, &mut TranspositionTable::new(1)).unwrap();
+    assert_eq!(score, 0);
+}
+
+#[test]
+fn test_repetition_draw() {
+    use crate::uci::{parse_command, Command};
+
+    // white is a queen down and can only save the game by repeating the position
+    let cmd = "position fen 4k3/8/8/8/8/8/q7/4K1N1 w - - 0 1 moves \
+               g1f3 e8d8 f3g1 d8e8 g1f3 e8d8 f3g1 d8e8";
+    let game = match parse_command(cmd) {
+        Some(Command::Position(g)) => g,
+        _ => unreachable!(),
+    };
+    let (m, score) = best_move(&game, 1, &mut TranspositionTable::new(1)).unwrap();
+    assert_eq!("g1f3", m.to_string());
+    assert_eq!(0, score);
+}
+
+#[test]
+fn test_quiescence_limits() {
+    use crate::uci::{parse_command, Command};
+
+    let mut tt = TranspositionTable::new(1);
+    let mut search = Search::new(&mut tt);
+
+    // the evasions are not searched past the maximum ply
+    let fen = "4k3/8/8/8/8/8/8/4QK2 b - - 0 1";
+    let mut game = Game::from_fen_str(fen);
+    let stand_pat = evaluate(&game.board) * game.player.to_int();
+    assert_eq!(stand_pat, search.quiescence(&mut game, MAX_PLY as u32, -INFINITY, INFINITY));
+
+    // the position already occurred inside the search
+    let cmd = "position fen 4k3/8/8/8/8/8/q7/4K1N1 w - - 0 1 moves g1f3 e8d8 f3g1 d8e8";
+    let mut game = match parse_command(cmd) {
+        Some(Command::Position(g)) => g,
+        _ => unreachable!(),
+    };
+    assert_eq!(0, search.quiescence(&mut game, 4, -INFINITY, INFINITY));
+    assert!(search.quiescence(&mut game, 3, -INFINITY, INFINITY) < -500);
+}
+
+#[test]
+fn test_transposition_table() {
+    let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    let game = Game::from_fen_str(fen);
+    let mut tt = TranspositionTable::new(1);
+
+    let (m, score) = best_move(&game, 2, &mut tt).unwrap();
+    let entry = *tt.probe(game.hash).unwrap();
+    assert_eq!(Some(m), entry.best_move);
+    assert_eq!(2, entry.depth);
+
+    // the second search is answered from the table except at the root
+    let (m2, score2) = best_move(&game, 2, &mut tt).unwrap();
+    assert_eq!(m, m2);
+    assert_eq!(score, score2);
+    assert_eq!(
+        (m, score),
+        best_move(&game, 2, &mut TranspositionTable::new(1)).unwrap()
+    );
+}
+
+/// Plain negamax without pruning nor transposition table
+#[cfg(test)]
+fn negamax_reference(search: &mut Search, game: &mut Game, depth: u32, max_depth: u32) -> i32 {
+    if depth == max_depth {
+        return search.quiescence(game, depth, -INFINITY, INFINITY);
+    }
+
+    let mut best_score = None;
+    for m in enumerate_moves(game) {
+        m.make(game);
+        if !is_in_check(&game.board, game.player.opposite()) {
+            let score = -negamax_reference(search, game, depth + 1, max_depth);
+            best_score = Some(best_score.map_or(score, |s: i32| s.max(score)));
+        }
+        m.unmake(game);
+    }
+
+    best_score.unwrap_or_else(|| {
+        if is_in_check(&game.board, game.player) {
+            -MATE_SCORE + depth as i32
+        } else {
+            0
+        }
+    })
+}
+
+#[test]
+fn test_alphabeta_matches_negamax() {
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+    for fen in fens.iter() {
+        let mut game = Game::from_fen_str(fen);
+        let mut tt = TranspositionTable::new(1);
+        let mut search = Search::new(&mut tt);
+        // the reference runs the same quiescence search with a full window, the
+        // delta pruning depends on alpha but the SEE pruning does not
+        search.set_selectivity(Selectivity::none());
+        let score = search.iterative_deepening(&game, 3, |_| true).unwrap().score;
+        assert_eq!(negamax_reference(&mut search, &mut game, 0, 3), score, "{}", fen);
+    }
+}
+
+#[test]
+fn test_mate_in_one() {
+    let fen = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+    let game = Game::from_fen_str(fen);
+    let (m, score) = best_move(&game, 2, &mut TranspositionTable::new(1)).unwrap();
+    assert_eq!("a1a8", m.to_string());
+    assert_eq!(MATE_SCORE - 1, score);
+    assert_eq!(Some(1), mate_in(score));
+}
+
+#[test]
+fn test_mate_in() {
+    assert_eq!(None, mate_in(0));
+    assert_eq!(None, mate_in(-5000));
+    assert_eq!(Some(1), mate_in(MATE_SCORE - 1));
+    assert_eq!(Some(2), mate_in(MATE_SCORE - 3));
+    assert_eq!(Some(-1), mate_in(-MATE_SCORE + 2));
+    assert_eq!(Some(-2), mate_in(-MATE_SCORE + 4));
+
+    // a mate found at ply 3 is stored as a mate from the position
+    assert_eq!(MATE_SCORE - 2, score_to_tt(MATE_SCORE - 5, 3));
+    assert_eq!(MATE_SCORE - 5, score_from_tt(MATE_SCORE - 2, 3));
+    assert_eq!(-MATE_SCORE + 1, score_to_tt(-MATE_SCORE + 4, 3));
+    assert_eq!(-MATE_SCORE + 4, score_from_tt(-MATE_SCORE + 1, 3));
+    assert_eq!(42, score_to_tt(42, 3));
+}
+
+#[test]
+fn test_mate_distance() {
+    // the mate in one is still the one found by a deeper search
+    let fen = "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1";
+    let game = Game::from_fen_str(fen);
+    let (m, score) = best_move(&game, 6, &mut TranspositionTable::new(1)).unwrap();
+    assert_eq!("a1a8", m.to_string());
+    assert_eq!(MATE_SCORE - 1, score);
+}
+
+#[test]
+fn test_check_extension() {
+    // smothered mate in four where each move of white is a check: Nf7+ Kg8
+    // Nh6+ Kh8 Qg8+ Rxg8 Nf7#
+    let fen = "4r2k/6pp/8/3QN3/8/8/6PP/6K1 w - - 0 1";
+    let game = Game::from_fen_str(fen);
+    let score = |selectivity| {
+        let mut tt = TranspositionTable::new(1);
+        let mut search = Search::new(&mut tt);
+        search.set_selectivity(selectivity);
+        search.iterative_deepening(&game, 4, |_| true).unwrap().score
+    };
+    assert_eq!(None, mate_in(score(Selectivity::none())));
+    let extensions = Selectivity {
+        extensions: true,
+        ..Selectivity::none()
+    };
+    assert_eq!(Some(4), mate_in(score(extensions)));
+}
+
+#[test]
+fn test_extended_moves() {
+    use crate::uci::parse_move;
+
+    let fen = "4k3/1p6/8/P3P3/8/8/5P2/4K3 w - - 0 1";
+    let mut game = Game::from_fen_str(fen);
+    let pawn = Piece::new(PieceType::Pawn, Color::White);
+    let mut passed = |m: &str| {
+        let m = parse_move(&game, m).unwrap();
+        m.make(&mut game);
+        let res = is_passed_pawn_push(&game.board, m, pawn);
+        m.unmake(&mut game);
+        res
+    };
+    assert!(passed("e5e6"));
+    // the pawn on b7 can still stop it
+    assert!(!passed("a5a6"));
+    assert!(!passed("f2f4"));
+
+    let fen = "4k3/8/8/3n4/8/4B3/3P4/4K3 b - - 0 1";
+    let mut game = Game::from_fen_str(fen);
+    let knight = Piece::new(PieceType::Knight, Color::Black);
+    let previous = parse_move(&game, "d5e3").unwrap();
+    previous.make(&mut game);
+    assert!(is_recapture(parse_move(&game, "d2e3").unwrap(), Some((previous, knight))));
+    assert!(!is_recapture(parse_move(&game, "e1e2").unwrap(), Some((previous, knight))));
+
+    // the knight did not capture anything
+    let fen = "4k3/8/8/3n4/8/8/3P4/4K3 b - - 0 1";
+    let mut game = Game::from_fen_str(fen);
+    let previous = parse_move(&game, "d5e3").unwrap();
+    previous.make(&mut game);
+    assert!(!is_recapture(parse_move(&game, "d2e3").unwrap(), Some((previous, knight))));
+}
+
+#[test]
+fn test_quiescence() {
+    // the pawn is defended by the king
+    let fen = "4k3/3p4/8/8/8/8/8/3QK3 w - - 0 1";
+    let game = Game::from_fen_str(fen);
+    let (m, score) = best_move(&game, 1, &mut TranspositionTable::new(1)).unwrap();
+    assert_ne!("d1d7", m.to_string());
+    assert!(score > 500);
+
+    // the capture wins a rook even though the queen is taken back
+    let fen = "3rk3/3r4/8/8/8/8/8/3QK3 b - - 0 1";
+    let mut game = Game::from_fen_str(fen);
+    let mut tt = TranspositionTable::new(1);
+    let score = Search::new(&mut tt).quiescence(&mut game, 0, -INFINITY, INFINITY);
+    assert!(score > 400, "{}", score);
+}
+
+#[test]
+fn test_iterative_deepening() {
+    let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+    let game = Game::from_fen_str(fen);
+    let mut tt = TranspositionTable::new(1);
+    let mut depths = Vec::new();
+
+    let iteration = Search::new(&mut tt)
+        .iterative_deepening(&game, 3, |it| {
+            depths.push(it.depth);
+            true
+        })
+        .unwrap();
+    assert_eq!(vec![1, 2, 3], depths);
+    assert_eq!(3, iteration.depth);
+
+    // the principal variation is a sequence of legal moves
+    assert!(!iteration.pv.is_empty());
+    let mut g = game.clone();
+    for m in iteration.pv.iter() {
+        let m = *enumerate_moves(&g).iter().find(|x| *x == m).unwrap();
+        m.make(&mut g);
+        assert!(!is_in_check(&g.board, g.player.opposite()));
+    }
+}
+
+#[test]
+fn test_stop_search() {
+    let game = Game::new();
+    let mut tt = TranspositionTable::new(1);
+
+    // stopping between iterations
+    let iteration = Search::new(&mut tt)
+        .iterative_deepening(&game, 10, |it| it.depth < 2)
+        .unwrap();
+    assert_eq!(2, iteration.depth);
+
+    // stopping during an iteration returns the last completed one
+    let mut search = Search::new(&mut tt);
+    search.set_stop_condition(|nodes| nodes >= 5000);
+    let iteration = search.iterative_deepening(&game, 10, |_| true).unwrap();
+    assert!(iteration.depth < 10);
+    assert!(iteration.nodes < 5000);
+}
+
+#[test]
+fn test_multipv() {
+    let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+    let game = Game::from_fen_str(fen);
+    let mut tt = TranspositionTable::new(1);
+    let mut lines = Vec::new();
+
+    let mut search = Search::new(&mut tt);
+    search.set_multipv(3);
+    let best = search
+        .iterative_deepening(&game, 2, |it| {
+            if it.depth == 2 {
+                lines.push(it.clone());
             }
+            true
+        })
+        .unwrap();
 
-            n += x;
+    assert_eq!(vec![1, 2, 3], lines.iter().map(|l| l.multipv).collect::<Vec<_>>());
+    assert_eq!(best.pv, lines[0].pv);
+    assert_ne!(lines[0].best_move(), lines[1].best_move());
+    assert_ne!(lines[1].best_move(), lines[2].best_move());
+    assert_ne!(lines[0].best_move(), lines[2].best_move());
+    assert!(lines[0].score >= lines[1].score && lines[1].score >= lines[2].score);
 
-            m.unmake(game);
+    // all the lines are searched at the same depth
+    let (_, score) = best_move(&game, 2, &mut TranspositionTable::new(1)).unwrap();
+    assert_eq!(score, lines[0].score);
+}
+
+#[test]
+fn test_aspiration_windows() {
+    // the score of this position changes a lot from one iteration to the next
+    let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    let game = Game::from_fen_str(fen);
+    let mut tt = TranspositionTable::new(1);
+    let mut reports = Vec::new();
+
+    let iteration = Search::new(&mut tt)
+        .iterative_deepening(&game, 6, |it| {
+            reports.push((it.depth, it.bound));
+            true
+        })
+        .unwrap();
+    assert_eq!(Bound::Exact, iteration.bound);
+
+    assert!(reports.iter().any(|(_, bound)| *bound != Bound::Exact));
+    // the first iterations are searched with a full window, then each
+    // failure is followed by a search of the same depth
+    for (i, (depth, bound)) in reports.iter().enumerate() {
+        if *bound != Bound::Exact {
+            assert!(*depth >= ASPIRATION_DEPTH);
+            assert_eq!(*depth, reports[i + 1].0);
         }
-        n
     }
-}
\ No newline at end of file
+    let depths: Vec<u32> = reports
+        .iter()
+        .filter(|(_, bound)| *bound == Bound::Exact)
+        .map(|(depth, _)| *depth)
+        .collect();
+    assert_eq!((1..=6).collect::<Vec<_>>(), depths);
+}
+
+#[test]
+fn test_contempt() {
+    let fen = "7k/8/8/8/8/8/8/K7 w - - 99 80";
+    let game = Game::from_fen_str(fen);
+    let mut tt = TranspositionTable::new(1);
+
+    let mut search = Search::new(&mut tt);
+    search.set_contempt(20);
+    let iteration = search.iterative_deepening(&game, 2, |_| true).unwrap();
+    assert_eq!(-20, iteration.score);
+}
+
+#[test]
+fn test_move_ordering_nodes() {
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+        "2r3k1/pp3ppp/2n1b3/3p4/3P4/2NB1N2/PP3PPP/2R3K1 b - - 3 20",
+    ];
+    let nodes = |ordering: bool| -> u64 {
+        fens.iter()
+            .map(|fen| {
+                let game = Game::from_fen_str(fen);
+                let mut tt = TranspositionTable::new(1);
+                let mut search = Search::new(&mut tt);
+                search.ordering = ordering;
+                search.iterative_deepening(&game, 3, |_| true).unwrap().nodes
+            })
+            .sum()
+    };
+    let (ordered, unordered) = (nodes(true), nodes(false));
+    assert!(ordered * 2 < unordered, "{} nodes, {} without ordering", ordered, unordered);
+}
+
+#[test]
+fn test_selectivity_nodes() {
+    let fen = "2r3k1/pp3ppp/2n1b3/3p4/3P4/2NB1N2/PP3PPP/2R3K1 b - - 3 20";
+    let game = Game::from_fen_str(fen);
+    let nodes = |selectivity: Selectivity| -> u64 {
+        let mut tt = TranspositionTable::new(1);
+        let mut search = Search::new(&mut tt);
+        search.set_selectivity(selectivity);
+        search.iterative_deepening(&game, 6, |_| true).unwrap().nodes
+    };
+
+    let none = nodes(Selectivity::none());
+    let default = Selectivity::default();
+    let default_nodes = nodes(default);
+    assert!(default_nodes * 4 < none, "{} nodes, {} without selectivity", default_nodes, none);
+    // the techniques overlap, each of them is measured by turning it off
+    for other in [
+        Selectivity { null_move: false, ..default },
+        Selectivity { lmr: false, ..default },
+        Selectivity { futility: false, ..default },
+        Selectivity { lmp: false, ..default },
+    ] {
+        let n = nodes(other);
+        assert!(default_nodes < n, "{:?}", other);
+    }
+}
+
+#[test]
+fn test_null_move_zugzwang() {
+    // in pawn endings passing is often better than any move (zugzwang), the
+    // null move search would then give wrong cutoffs
+    let fen = "8/8/8/8/5k2/8/4pK2/8 w - - 0 1";
+    let game = Game::from_fen_str(fen);
+    assert!(!has_non_pawn_material(&game.board, Color::Black));
+    assert!(has_non_pawn_material(&Board::starting_board(), Color::White));
+
+    // white wins the pawn with Kd6-e7, without the check for pieces other than
+    // pawns the null move search gives wrong cutoffs and Kd4 is played
+    let fen = "8/5p2/5P2/4K3/8/8/6k1/8 w - - 0 1";
+    let game = Game::from_fen_str(fen);
+    let search = |null_move| {
+        let mut tt = TranspositionTable::new(1);
+        let mut search = Search::new(&mut tt);
+        search.set_selectivity(Selectivity {
+            null_move,
+            ..Default::default()
+        });
+        let it = search.iterative_deepening(&game, 6, |_| true).unwrap();
+        (it.best_move().to_string(), it.score)
+    };
+    assert_eq!(("e5d6".to_string(), 100), search(false));
+    assert_eq!(search(false), search(true));
+}
+
@@ -0,0 +1,204 @@
+use crate::{
+    bitboard::{bishop_attacks, king_attacks, knight_attacks, pawn_attacks, rook_attacks, Bitboard},
+    board::{Board, Color, PieceType, RANK_1, RANK_8},
+    eval::piece_value,
+    game::Game,
+    moves::Move,
+    square::Square,
+};
+
+/// Attackers are tried from the least valuable
+const ATTACKER_ORDER: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+
+/// Pieces of both colors attacking `square`, with the pieces outside of
+/// `occupied` removed from the board
+pub fn attackers_to(board: &Board, square: Square, occupied: Bitboard) -> Bitboard {
+    let index = square.index();
+    let pieces = |typ| board.pieces(typ, Color::White) | board.pieces(typ, Color::Black);
+    let queens = pieces(PieceType::Queen);
+
+    let attackers = pawn_attacks(Color::Black, index) & board.pieces(PieceType::Pawn, Color::White)
+        | pawn_attacks(Color::White, index) & board.pieces(PieceType::Pawn, Color::Black)
+        | knight_attacks(index) & pieces(PieceType::Knight)
+        | king_attacks(index) & pieces(PieceType::King)
+        | bishop_attacks(index, occupied) & (pieces(PieceType::Bishop) | queens)
+        | rook_attacks(index, occupied) & (pieces(PieceType::Rook) | queens);
+    attackers & occupied
+}
+
+/// Material won by capturing on `square` with a piece of type `typ`, on top of
+/// the captured piece: a pawn reaching the last rank becomes a queen
+fn promotion_gain(typ: PieceType, square: Square) -> i32 {
+    if typ == PieceType::Pawn && (square.rank() == RANK_1 || square.rank() == RANK_8) {
+        piece_value(PieceType::Queen) - piece_value(PieceType::Pawn)
+    } else {
+        0
+    }
+}
+
+/// Least valuable piece of `side` among `attackers` and its bitboard
+fn least_valuable(board: &Board, attackers: Bitboard, side: Color) -> Option<(PieceType, Bitboard)> {
+    let ours = attackers & board.color(side);
+    ATTACKER_ORDER
+        .iter()
+        .map(|typ| (*typ, ours & board.pieces(*typ, side)))
+        .find(|(_, bb)| *bb != 0)
+        .map(|(typ, bb)| (typ, bb & bb.wrapping_neg()))
+}
+
+/// Occupancy after `m`, material it wins and value of the piece it leaves on
+/// the destination
+fn play(board: &Board, m: Move) -> (Bitboard, i32, i32) {
+    let (from, to) = (m.origin(), m.destination());
+
+    let mut occupied = board.occupied() & !from.bitboard();
+    if m.is_en_passant() {
+        occupied &= !Square::new_nocheck(to.file(), from.rank()).bitboard();
+    }
+
+    let captured = m.captured(board).map_or(0, piece_value);
+    match m.promotion() {
+        Some(typ) => (
+            occupied,
+            captured + piece_value(typ) - piece_value(PieceType::Pawn),
+            piece_value(typ),
+        ),
+        None => (occupied, captured, piece_value(m.piece_type(board))),
+    }
+}
+
+/// Static exchange evaluation: material won by the player to move if `m` is
+/// followed by the sequence of captures on its destination where each side
+/// recaptures with its least valuable piece, and may stop capturing when it
+/// would lose material. Pieces behind a capturing slider join the exchange.
+/// Pins are not taken into account, a king capturing a defended piece is
+/// simply scored as losing the king.
+pub fn see(game: &Game, m: Move) -> i32 {
+    let board = &game.board;
+    let to = m.destination();
+    let color = board.get(m.origin()).expect("the from square is empty").color;
+
+    // gain[d] is the material won by the side making the d-th capture if the
+    // exchange stops after it
+    let mut gain = [0; 32];
+    let (mut occupied, first_gain, mut on_square) = play(board, m);
+    gain[0] = first_gain;
+
+    let mut attackers = attackers_to(board, to, occupied);
+    let mut side = color.opposite();
+    let mut d = 0;
+
+    while let Some((typ, bb)) = least_valuable(board, attackers, side) {
+        let promotion = promotion_gain(typ, to);
+        d += 1;
+        gain[d] = on_square + promotion - gain[d - 1];
+        on_square = piece_value(typ) + promotion;
+
+        occupied &= !bb;
+        // reveal the sliders behind the piece that captured
+        attackers = attackers_to(board, to, occupied);
+        side = side.opposite();
+    }
+
+    while d > 0 {
+        gain[d - 1] = -(-gain[d - 1]).max(gain[d]);
+        d -= 1;
+    }
+    gain[0]
+}
+
+/// Returns true if the static exchange evaluation of `m` is at least
+/// `threshold`. Stops as soon as the side to capture next cannot change the
+/// outcome, instead of computing the whole exchange.
+pub fn see_ge(game: &Game, m: Move, threshold: i32) -> bool {
+    let board = &game.board;
+    let to = m.destination();
+    let color = board.get(m.origin()).expect("the from square is empty").color;
+
+    let (mut occupied, first_gain, mut on_square) = play(board, m);
+    // material won by the side that captured last compared to the threshold,
+    // as seen by that side, if the exchange stops here
+    let mut balance = first_gain - threshold;
+    if balance < 0 {
+        return false;
+    }
+
+    let mut side = color;
+    loop {
+        let attackers = attackers_to(board, to, occupied);
+        let other = side.opposite();
+        let (typ, bb) = match least_valuable(board, attackers, other) {
+            Some(attacker) => attacker,
+            None => return side == color,
+        };
+
+        let promotion = promotion_gain(typ, to);
+        balance = on_square + promotion - balance;
+        on_square = piece_value(typ) + promotion;
+        // the capture does not help if the exchange stopping right after it
+        // still leaves the outcome to the side that captured last
+        let improves = if other == color { balance >= 0 } else { balance > 0 };
+        if !improves {
+            return side == color;
+        }
+
+        occupied &= !bb;
+        side = other;
+    }
+}
+
+#[cfg(test)]
+fn see_str(fen: &str, m: &str) -> i32 {
+    let game = Game::from_fen_str(fen);
+    see(&game, crate::uci::parse_move(&game, m).unwrap())
+}
+
+#[test]
+fn test_see() {
+    // undefended pawn
+    assert_eq!(100, see_str("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"));
+    // knight for a pawn once the queens behind the rook and the bishop join in
+    let fen = "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1";
+    assert_eq!(100 - 350, see_str(fen, "d3e5"));
+    // the second rook recaptures through the first one
+    assert_eq!(100, see_str("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"));
+    assert_eq!(100 - 525, see_str("3rk3/8/8/3p4/8/8/3R4/4K3 w - - 0 1", "d2d5"));
+    // the king only recaptures once the square is no longer defended
+    let fen = "4k3/8/8/b7/8/8/3q4/3RK3 w - - 0 1";
+    assert_eq!(1000 - 525 + 350, see_str(fen, "d1d2"));
+    let game = Game::from_fen_str(fen);
+    let king_takes = Move::new(&game, Square::from_name("e1"), Square::from_name("d2"), None);
+    assert!(see(&game, king_takes) < 0);
+    assert_eq!(0, see_str("4k3/8/2p5/3p4/4P3/8/8/4K3 w - - 0 1", "e4d5"));
+    // a quiet move to an attacked square loses the piece
+    assert_eq!(-350, see_str("4k3/8/4p3/8/8/2N5/8/4K3 w - - 0 1", "c3d5"));
+    // en passant and promotion
+    assert_eq!(100, see_str("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", "e5d6"));
+    assert_eq!(1000 - 100, see_str("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"));
+    assert_eq!(-100, see_str("2r1k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"));
+    // the pawn recapturing on the last rank promotes
+    assert_eq!(-1000 + 100, see_str("1R1rk3/P7/8/8/8/8/8/4K3 b - - 0 1", "d8b8"));
+}
+
+#[test]
+fn test_see_ge() {
+    let fen = "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1";
+    let game = Game::from_fen_str(fen);
+    let m = crate::uci::parse_move(&game, "d3e5").unwrap();
+    assert!(see_ge(&game, m, -250));
+    assert!(!see_ge(&game, m, -249));
+    assert!(!see_ge(&game, m, 0));
+
+    let fen = "1R1rk3/P7/8/8/8/8/8/4K3 b - - 0 1";
+    let game = Game::from_fen_str(fen);
+    let m = crate::uci::parse_move(&game, "d8b8").unwrap();
+    assert!(see_ge(&game, m, -900));
+    assert!(!see_ge(&game, m, -899));
+}
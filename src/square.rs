@@ -1,3 +1,7 @@
+use std::fmt;
+
+use crate::bitboard::Bitboard;
+
 use crate::board::{FILE_A, FILE_B, FILE_C, FILE_D, FILE_E, FILE_F, FILE_G, FILE_H, RANK_1, RANK_2, RANK_3, RANK_4, RANK_5, RANK_6, RANK_7, RANK_8};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -16,6 +20,17 @@ impl Square {
         Square(file, rank)
     }
 
+    /// Square from its name such as "e4", panics if the name is invalid
+    #[cfg(test)]
+    pub fn from_name(name: &str) -> Self {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(file), Some(rank), None) => Square::from_chars(file, rank),
+            _ => None,
+        }
+        .unwrap_or_else(|| panic!("invalid square {}", name))
+    }
+
     pub fn from_chars(file: char, rank: char) -> Option<Self> {
         let filei = match file {
             'a' => FILE_A,
@@ -44,6 +59,15 @@ impl Square {
         Some(Square(filei, ranki))
     }
 
+    /// Inverse of `index`
+    pub fn from_index(index: u8) -> Square {
+        Square(index % 8, index / 8)
+    }
+
+    pub fn bitboard(&self) -> Bitboard {
+        1 << self.index()
+    }
+
     pub fn index(&self) -> usize {
         let Square(file, rank) = *self;
         8 * rank as usize + file as usize
@@ -55,7 +79,7 @@ impl Square {
         let new_file = file as i8 + off_file;
         let new_rank = rank as i8 + off_rank;
 
-        if new_file < 0 || new_file > 7 || new_rank < 0 || new_rank > 7 {
+        if !(0..=7).contains(&new_file) || !(0..=7).contains(&new_rank) {
             None
         } else {
             Some(Square::new_nocheck(new_file as u8, new_rank as u8))
@@ -69,8 +93,10 @@ impl Square {
     pub fn rank(&self) -> u8 {
         self.1
     }
+}
 
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let Square(file, rank) = *self;
         let file_c = match file {
             FILE_A => 'a',
@@ -96,10 +122,7 @@ impl Square {
             _ => unreachable!(),
         };
 
-        let mut res = String::with_capacity(2);
-        res.push(file_c);
-        res.push(rank_c);
-        res
+        write!(f, "{}{}", file_c, rank_c)
     }
 }
 
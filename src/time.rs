@@ -0,0 +1,156 @@
+use std::{
+    cell::Cell,
+    cmp::min,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    board::Color,
+    search::{mate_in, Iteration, MAX_PLY},
+    uci::GoParams,
+};
+
+/// Default time kept in reserve for the communication with the GUI
+pub const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+/// Number of moves the remaining time is divided by when `movestogo` is unknown
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// Turns the `go` arguments into limits for the search. The soft limit is
+/// checked between iterations, since the next iteration usually takes longer
+/// than all the previous ones together no new iteration is started after half
+/// of it. The hard limit aborts the current iteration. While pondering the
+/// limits are ignored by the caller, which restarts them on `ponderhit`.
+#[derive(Clone, Debug)]
+pub struct TimeManager {
+    start: Cell<Instant>,
+    soft_limit: Option<Duration>,
+    hard_limit: Option<Duration>,
+    max_depth: u32,
+    max_nodes: Option<u64>,
+    mate: Option<u32>,
+}
+
+impl TimeManager {
+    pub fn new(params: &GoParams, player: Color, move_overhead: Duration) -> Self {
+        let (time, inc) = match player {
+            Color::White => (params.wtime, params.winc),
+            Color::Black => (params.btime, params.binc),
+        };
+
+        let (soft_limit, hard_limit) = if params.infinite {
+            (None, None)
+        } else if let Some(movetime) = params.movetime {
+            let limit = movetime.saturating_sub(move_overhead);
+            (Some(limit), Some(limit))
+        } else if let Some(time) = time {
+            let available = time.saturating_sub(move_overhead);
+            let moves_to_go = params.movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+            let target = min(available / moves_to_go + inc.unwrap_or_default(), available);
+            (Some(target), Some(min(target * 3, available)))
+        } else {
+            (None, None)
+        };
+
+        let mut max_depth = params.depth.unwrap_or(MAX_PLY as u32);
+        if let Some(mate) = params.mate {
+            // A mate in n moves is found by a search of 2n - 1 plies
+            max_depth = min(max_depth, (2 * mate).saturating_sub(1).max(1));
+        }
+
+        TimeManager {
+            start: Cell::new(Instant::now()),
+            soft_limit,
+            hard_limit,
+            max_depth,
+            max_nodes: params.nodes,
+            mate: params.mate,
+        }
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.get().elapsed()
+    }
+
+    /// Measure the limits from now on, the search started when pondering
+    /// becomes the normal search on `ponderhit`
+    pub fn restart(&self) {
+        self.start.set(Instant::now());
+    }
+
+    /// Polled during the search with the number of searched nodes
+    pub fn must_stop(&self, nodes: u64) -> bool {
+        self.max_nodes.is_some_and(|max| nodes >= max)
+            || self.hard_limit.is_some_and(|limit| self.elapsed() >= limit)
+    }
+
+    /// Called after each completed iteration, returns false if the next one
+    /// should not be started
+    pub fn continue_search(&self, iteration: &Iteration) -> bool {
+        if let Some(mate) = self.mate {
+            if mate_in(iteration.score).is_some_and(|n| n > 0 && n as u32 <= mate) {
+                return false;
+            }
+        }
+        if self.max_nodes.is_some_and(|max| iteration.nodes >= max) {
+            return false;
+        }
+        self.soft_limit.is_none_or(|limit| self.elapsed() < limit / 2)
+    }
+}
+
+#[cfg(test)]
+fn params(cmd: &str) -> GoParams {
+    match crate::uci::parse_command(cmd) {
+        Some(crate::uci::Command::Go(params)) => params,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_time_allocation() {
+    let tm = TimeManager::new(&params("go wtime 60000 btime 1000 movestogo 20"), Color::White, MOVE_OVERHEAD);
+    assert_eq!(Some((Duration::from_millis(60000) - MOVE_OVERHEAD) / 20), tm.soft_limit);
+    assert_eq!(Some((Duration::from_millis(60000) - MOVE_OVERHEAD) * 3 / 20), tm.hard_limit);
+
+    // The increment is added but the limits never exceed the remaining time
+    let tm = TimeManager::new(&params("go wtime 60000 btime 1000 binc 5000"), Color::Black, MOVE_OVERHEAD);
+    assert_eq!(Some(Duration::from_millis(1000) - MOVE_OVERHEAD), tm.soft_limit);
+    assert_eq!(Some(Duration::from_millis(1000) - MOVE_OVERHEAD), tm.hard_limit);
+
+    let tm = TimeManager::new(&params("go wtime 10 btime 10"), Color::White, MOVE_OVERHEAD);
+    assert_eq!(Some(Duration::from_millis(0)), tm.hard_limit);
+    assert!(tm.must_stop(0));
+}
+
+#[test]
+fn test_limits() {
+    let tm = TimeManager::new(&params("go movetime 1000 wtime 10 btime 10"), Color::White, MOVE_OVERHEAD);
+    assert_eq!(Some(Duration::from_millis(1000) - MOVE_OVERHEAD), tm.hard_limit);
+
+    let tm = TimeManager::new(&params("go infinite wtime 10 btime 10"), Color::White, MOVE_OVERHEAD);
+    assert_eq!(None, tm.hard_limit);
+    assert_eq!(MAX_PLY as u32, tm.max_depth());
+    assert!(!tm.must_stop(u64::MAX));
+
+    let tm = TimeManager::new(&params("go depth 5 nodes 1000"), Color::White, MOVE_OVERHEAD);
+    assert_eq!(5, tm.max_depth());
+    assert!(!tm.must_stop(999));
+    assert!(tm.must_stop(1000));
+
+    let tm = TimeManager::new(&params("go mate 2"), Color::White, MOVE_OVERHEAD);
+    assert_eq!(3, tm.max_depth());
+}
+
+#[test]
+fn test_restart() {
+    let tm = TimeManager::new(&params("go ponder movetime 50"), Color::White, Duration::ZERO);
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(tm.must_stop(0));
+    tm.restart();
+    assert!(!tm.must_stop(0));
+    assert!(tm.elapsed() < Duration::from_millis(50));
+}
@@ -0,0 +1,158 @@
+use std::mem::size_of;
+
+use crate::moves::Move;
+
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    /// The score is at least `score`
+    Lower,
+    /// The score is at most `score`
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub key: u64,
+    /// Remaining depth of the search that produced this entry
+    pub depth: u32,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+    pub age: u8,
+}
+
+/// Fixed-size hash table indexed by the Zobrist key of the position
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    mask: usize,
+    age: u8,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let max_entries = (size_mb * 1024 * 1024 / size_of::<Option<Entry>>()).max(1);
+        // largest power of two that fits in the requested size
+        let len = 1 << (usize::BITS - 1 - max_entries.leading_zeros());
+
+        TranspositionTable {
+            entries: vec![None; len],
+            mask: len - 1,
+            age: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for e in self.entries.iter_mut() {
+            *e = None;
+        }
+        self.age = 0;
+    }
+
+    /// Called before each search so that entries from older searches are replaced first
+    pub fn new_search(&mut self) {
+        self.age = self.age.wrapping_add(1);
+    }
+
+    /// Permille of the entries written by the current search, estimated on the
+    /// first thousand entries as in the UCI `hashfull` info
+    pub fn hashfull(&self) -> u32 {
+        let sample = &self.entries[..self.entries.len().min(1000)];
+        let used = sample
+            .iter()
+            .filter(|e| matches!(e, Some(e) if e.age == self.age))
+            .count();
+        (used * 1000 / sample.len()) as u32
+    }
+
+    pub fn probe(&self, key: u64) -> Option<&Entry> {
+        match &self.entries[key as usize & self.mask] {
+            Some(e) if e.key == key => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn store(
+        &mut self,
+        key: u64,
+        depth: u32,
+        score: i32,
+        bound: Bound,
+        best_move: Option<Move>,
+    ) {
+        let age = self.age;
+        let slot = &mut self.entries[key as usize & self.mask];
+
+        // keep the deepest entry of the current search, unless the position is the same
+        let replace = match slot {
+            None => true,
+            Some(e) => e.key == key || e.age != age || depth >= e.depth,
+        };
+
+        if replace {
+            // do not lose the best move of a position when storing a shallower result without one
+            let best_move = match (best_move, &slot) {
+                (None, Some(e)) if e.key == key => e.best_move,
+                _ => best_move,
+            };
+
+            *slot = Some(Entry {
+                key,
+                depth,
+                score,
+                bound,
+                best_move,
+                age,
+            });
+        }
+    }
+}
+
+#[test]
+fn test_size() {
+    let tt = TranspositionTable::new(1);
+    assert!(tt.entries.len().is_power_of_two());
+    assert!(tt.entries.len() * size_of::<Option<Entry>>() <= 1024 * 1024);
+    assert!(2 * tt.entries.len() * size_of::<Option<Entry>>() > 1024 * 1024);
+}
+
+#[test]
+fn test_store_probe() {
+    let mut tt = TranspositionTable::new(1);
+    let key = 0xdead_beef;
+    let other = key + tt.entries.len() as u64;
+
+    assert!(tt.probe(key).is_none());
+    tt.store(key, 3, 42, Bound::Exact, None);
+    assert_eq!(42, tt.probe(key).unwrap().score);
+
+    // same slot, shallower entry of the same search does not replace
+    tt.store(other, 2, 10, Bound::Lower, None);
+    assert!(tt.probe(other).is_none());
+    assert_eq!(42, tt.probe(key).unwrap().score);
+
+    // an entry from an older search is always replaced
+    tt.new_search();
+    tt.store(other, 1, 10, Bound::Lower, None);
+    assert!(tt.probe(key).is_none());
+    assert_eq!(Bound::Lower, tt.probe(other).unwrap().bound);
+
+    tt.clear();
+    assert!(tt.probe(other).is_none());
+}
+
+#[test]
+fn test_hashfull() {
+    let mut tt = TranspositionTable::new(1);
+    assert_eq!(0, tt.hashfull());
+    for key in 0..500 {
+        tt.store(key, 1, 0, Bound::Exact, None);
+    }
+    assert_eq!(500, tt.hashfull());
+
+    // entries of previous searches are not counted
+    tt.new_search();
+    assert_eq!(0, tt.hashfull());
+}
@@ -1,10 +1,13 @@
-use std::str::SplitAsciiWhitespace;
+use std::{io::Write, str::SplitAsciiWhitespace, time::Duration};
 
-use crate::{board::{FILE_A, FILE_C, FILE_D, FILE_E, FILE_F, FILE_G, FILE_H, RANK_1, RANK_8}, square::Square};
+use crate::square::Square;
 use crate::{
-    board::{Color, PieceType},
+    board::PieceType,
     game::Game,
+    log,
     moves::Move,
+    search::{mate_in, Iteration},
+    tt::Bound,
 };
 
 #[derive(Clone, Debug)]
@@ -13,67 +16,40 @@ pub enum Command {
     IsReady,
     NewGame,
     Position(Game),
-    Go(String), // TODO: something better
+    Go(GoParams),
+    SetOption { name: String, value: Option<String> },
+    Stop,
+    PonderHit,
     Quit,
     Perft(u32),
 }
 
-fn parse_move(game: &Game, str: &str) -> Option<Move> {
+/// Arguments of the `go` command, times are given in milliseconds
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GoParams {
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub movestogo: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    /// Search for a mate in this many moves
+    pub mate: Option<u32>,
+    pub infinite: bool,
+    pub ponder: bool,
+    /// Only search these root moves, kept as strings since they can only be
+    /// parsed against a position
+    pub searchmoves: Vec<String>,
+}
+
+/// Parse a move in long algebraic notation, only legal moves are accepted
+pub fn parse_move(game: &Game, str: &str) -> Option<Move> {
     let mut chars = str.chars();
 
-    let from_file = chars.next()?;
-    let from_rank = chars.next()?;
-    let from = Square::from_chars(from_file, from_rank)?;
-
-    let to_file = chars.next()?;
-    let to_rank = chars.next()?;
-    let to = Square::from_chars(to_file, to_rank)?;
-
-    if game.board.get(from)?.typ == PieceType::King {
-        match (from, to) {
-            (Square(FILE_E, RANK_1), Square(FILE_G, RANK_1)) => {
-                return Some(Move::Castling {
-                    from: from,
-                    to: to,
-                    from_rook: Square::new_nocheck(FILE_H, RANK_1),
-                    to_rook: Square::new_nocheck(FILE_F, RANK_1),
-                    color: Color::White,
-                    castling_rights: game.castling_rights,
-                })
-            },
-            (Square(FILE_E, RANK_1), Square(FILE_C, RANK_1)) => {
-                return Some(Move::Castling {
-                    from: from,
-                    to: to,
-                    from_rook: Square::new_nocheck(FILE_A, RANK_1),
-                    to_rook: Square::new_nocheck(FILE_D, RANK_1),
-                    color: Color::White,
-                    castling_rights: game.castling_rights,
-                })
-            },
-            (Square(FILE_E, RANK_8), Square(FILE_C, RANK_8)) => {
-                return Some(Move::Castling {
-                    from: from,
-                    to: to,
-                    from_rook: Square::new_nocheck(FILE_A, RANK_8),
-                    to_rook: Square::new_nocheck(FILE_D, RANK_8),
-                    color: Color::Black,
-                    castling_rights: game.castling_rights,
-                })
-            },
-            (Square(FILE_E, RANK_8), Square(FILE_G, RANK_8)) => {
-                return Some(Move::Castling {
-                    from: from,
-                    to: to,
-                    from_rook: Square::new_nocheck(FILE_H, RANK_8),
-                    to_rook: Square::new_nocheck(FILE_F, RANK_8),
-                    color: Color::Black,
-                    castling_rights: game.castling_rights,
-                })
-            }
-            _ => {},
-        }
-    }
+    let from = Square::from_chars(chars.next()?, chars.next()?)?;
+    let to = Square::from_chars(chars.next()?, chars.next()?)?;
 
     let promotion = match chars.next() {
         Some('q') => Some(PieceType::Queen),
@@ -83,8 +59,13 @@ fn parse_move(game: &Game, str: &str) -> Option<Move> {
         Some(_) => return None,
         None => None,
     };
+    if chars.next().is_some() {
+        return None;
+    }
 
-    Some(Move::new(game, from, to, promotion))
+    game.legal_moves()
+        .into_iter()
+        .find(|m| m.origin() == from && m.destination() == to && m.promotion() == promotion)
 }
 
 fn parse_position(split: &mut SplitAsciiWhitespace) -> Option<Command> {
@@ -108,14 +89,72 @@ fn parse_position(split: &mut SplitAsciiWhitespace) -> Option<Command> {
     Some(Command::Position(game))
 }
 
+fn parse_duration(tok: Option<&str>) -> Option<Duration> {
+    // Some GUIs send a negative time when the engine is late
+    let ms: i64 = tok?.parse().ok()?;
+    Some(Duration::from_millis(ms.max(0) as u64))
+}
+
 fn parse_go(split: &mut SplitAsciiWhitespace) -> Option<Command> {
-    match split.next() {
-        Some("perft") => {
-            let depth = split.next()?.parse().ok()?;
-            Some(Command::Perft(depth))
-        },
-        _ => Some(Command::Go("".to_owned())),
+    let mut params = GoParams::default();
+    let mut split = split.peekable();
+
+    while let Some(tok) = split.next() {
+        match tok {
+            "perft" => {
+                let depth = split.next()?.parse().ok()?;
+                return Some(Command::Perft(depth));
+            }
+            "wtime" => params.wtime = Some(parse_duration(split.next())?),
+            "btime" => params.btime = Some(parse_duration(split.next())?),
+            "winc" => params.winc = Some(parse_duration(split.next())?),
+            "binc" => params.binc = Some(parse_duration(split.next())?),
+            "movetime" => params.movetime = Some(parse_duration(split.next())?),
+            "movestogo" => params.movestogo = Some(split.next()?.parse().ok()?),
+            "depth" => params.depth = Some(split.next()?.parse().ok()?),
+            "nodes" => params.nodes = Some(split.next()?.parse().ok()?),
+            "mate" => params.mate = Some(split.next()?.parse().ok()?),
+            "infinite" => params.infinite = true,
+            "ponder" => params.ponder = true,
+            "searchmoves" => {
+                // The list ends at the next keyword
+                while let Some(m) = split.peek() {
+                    let mut chars = m.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(file), Some(rank)) if Square::from_chars(file, rank).is_some() => {}
+                        _ => break,
+                    }
+                    params.searchmoves.push(m.to_string());
+                    split.next();
+                }
+            }
+            _ => return None,
+        }
     }
+
+    Some(Command::Go(params))
+}
+
+fn parse_setoption(split: &mut SplitAsciiWhitespace) -> Option<Command> {
+    if split.next()? != "name" {
+        return None;
+    }
+
+    // both the name and the value can contain spaces
+    let mut name = Vec::new();
+    let mut value = None;
+    for tok in split {
+        match value.as_mut() {
+            None if tok == "value" => value = Some(Vec::new()),
+            None => name.push(tok),
+            Some(v) => v.push(tok),
+        }
+    }
+
+    Some(Command::SetOption {
+        name: name.join(" "),
+        value: value.map(|v| v.join(" ")),
+    })
 }
 
 pub fn parse_command(cmd: &str) -> Option<Command> {
@@ -125,12 +164,57 @@ pub fn parse_command(cmd: &str) -> Option<Command> {
         "isready" => Some(Command::IsReady),
         "ucinewgame" => Some(Command::NewGame),
         "go" => parse_go(&mut split),
+        "setoption" => parse_setoption(&mut split),
+        "stop" => Some(Command::Stop),
+        "ponderhit" => Some(Command::PonderHit),
         "quit" => Some(Command::Quit),
         "position" => parse_position(&mut split),
         _ => None,
     }
 }
 
+/// Send a line to the GUI
+pub fn send(line: &str) {
+    log::output(line);
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    // the GUI is gone if this fails, there is no one left to tell
+    let _ = writeln!(stdout, "{}", line);
+    let _ = stdout.flush();
+}
+
+/// `score` part of an `info` line, from the point of view of the engine
+pub fn format_score(score: i32, bound: Bound) -> String {
+    let mut res = match mate_in(score) {
+        Some(n) => format!("score mate {}", n),
+        None => format!("score cp {}", score),
+    };
+    match bound {
+        Bound::Exact => {}
+        Bound::Lower => res += " lowerbound",
+        Bound::Upper => res += " upperbound",
+    }
+    res
+}
+
+/// `info` line describing a completed iteration
+pub fn format_info(iteration: &Iteration, elapsed: Duration) -> String {
+    let ms = elapsed.as_millis() as u64;
+    let pv: Vec<String> = iteration.pv.iter().map(|m| m.to_string()).collect();
+    format!(
+        "info depth {} seldepth {} multipv {} {} nodes {} nps {} time {} hashfull {} pv {}",
+        iteration.depth,
+        iteration.seldepth,
+        iteration.multipv,
+        format_score(iteration.score, iteration.bound),
+        iteration.nodes,
+        iteration.nodes * 1000 / ms.max(1),
+        ms,
+        iteration.hashfull,
+        pv.join(" ")
+    )
+}
+
 #[test]
 fn parse_position1() {
     parse_command("position startpos moves e2e4").unwrap();
@@ -138,35 +222,155 @@ fn parse_position1() {
 
 #[test]
 fn test_parse_castling() {
-    let cmd = "position fen 8/8/8/8/8/8/8/4K2R b - - 11 34 moves e1g1";
+    let cmd = "position fen 4k3/8/8/8/8/8/8/4K2R w K - 11 34 moves e1g1";
     match parse_command(cmd) {
         Some(Command::Position(g)) => {
-            assert_eq!(g.board.to_fen(), "8/8/8/8/8/8/8/5RK1");
+            assert_eq!(g.board.to_fen(), "4k3/8/8/8/8/8/8/5RK1");
         },
         _ => unreachable!()
     }
 
-    let cmd = "position fen 8/8/8/8/8/8/8/R3K3 b - - 11 34 moves e1c1";
+    let cmd = "position fen 4k3/8/8/8/8/8/8/R3K3 w Q - 11 34 moves e1c1";
     match parse_command(cmd) {
         Some(Command::Position(g)) => {
-            assert_eq!(g.board.to_fen(), "8/8/8/8/8/8/8/2KR4");
+            assert_eq!(g.board.to_fen(), "4k3/8/8/8/8/8/8/2KR4");
         },
         _ => unreachable!()
     }
 
-    let cmd = "position fen 4k2r/8/8/8/8/8/8/8 b - - 11 34 moves e8g8";
+    let cmd = "position fen 4k2r/8/8/8/8/8/8/4K3 b k - 11 34 moves e8g8";
     match parse_command(cmd) {
         Some(Command::Position(g)) => {
-            assert_eq!(g.board.to_fen(), "5rk1/8/8/8/8/8/8/8");
+            assert_eq!(g.board.to_fen(), "5rk1/8/8/8/8/8/8/4K3");
         },
         _ => unreachable!()
     }
 
-    let cmd = "position fen r3k3/8/8/8/8/8/8/8 b - - 11 34 moves e8c8";
+    let cmd = "position fen r3k3/8/8/8/8/8/8/4K3 b q - 11 34 moves e8c8";
     match parse_command(cmd) {
         Some(Command::Position(g)) => {
-            assert_eq!(g.board.to_fen(), "2kr4/8/8/8/8/8/8/8");
+            assert_eq!(g.board.to_fen(), "2kr4/8/8/8/8/8/8/4K3");
         },
         _ => unreachable!()
     }
-}
\ No newline at end of file
+
+    // without the castling right the king only moves
+    assert!(parse_command("position fen 4k3/8/8/8/8/8/8/4K2R w - - 11 34 moves e1g1").is_none());
+}
+
+#[test]
+fn test_parse_en_passant() {
+    let cmd = "position startpos moves e2e4 a7a6 e4e5 d7d5";
+    let g = match parse_command(cmd) {
+        Some(Command::Position(g)) => g,
+        _ => unreachable!(),
+    };
+    assert_eq!(g.en_passant, Some(Square::from_name("d6")));
+
+    let cmd = "position startpos moves e2e4 a7a6 e4e5 d7d5 e5d6";
+    match parse_command(cmd) {
+        Some(Command::Position(g)) => {
+            assert_eq!(
+                g.board.to_fen(),
+                "rnbqkbnr/1pp1pppp/p2P4/8/8/8/PPPP1PPP/RNBQKBNR"
+            );
+            assert_eq!(g.en_passant, None);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_parse_go() {
+    let cmd = "go wtime 300000 btime 295000 winc 2000 binc 2000 movestogo 40";
+    let params = match parse_command(cmd) {
+        Some(Command::Go(params)) => params,
+        _ => unreachable!(),
+    };
+    assert_eq!(Some(Duration::from_millis(300000)), params.wtime);
+    assert_eq!(Some(Duration::from_millis(295000)), params.btime);
+    assert_eq!(Some(Duration::from_millis(2000)), params.winc);
+    assert_eq!(Some(Duration::from_millis(2000)), params.binc);
+    assert_eq!(Some(40), params.movestogo);
+    assert!(!params.infinite);
+
+    let cmd = "go searchmoves e2e4 d2d4 depth 5 nodes 10000 mate 3 infinite";
+    let params = match parse_command(cmd) {
+        Some(Command::Go(params)) => params,
+        _ => unreachable!(),
+    };
+    assert_eq!(vec!["e2e4", "d2d4"], params.searchmoves);
+    assert_eq!(Some(5), params.depth);
+    assert_eq!(Some(10000), params.nodes);
+    assert_eq!(Some(3), params.mate);
+    assert!(params.infinite);
+
+    match parse_command("go wtime -150 btime 1000") {
+        Some(Command::Go(params)) => assert_eq!(Some(Duration::from_millis(0)), params.wtime),
+        _ => unreachable!(),
+    }
+    match parse_command("go") {
+        Some(Command::Go(params)) => assert_eq!(GoParams::default(), params),
+        _ => unreachable!(),
+    }
+    assert!(parse_command("go depth x").is_none());
+    assert!(matches!(parse_command("go perft 3"), Some(Command::Perft(3))));
+}
+
+#[test]
+fn test_format_info() {
+    use crate::search::MATE_SCORE;
+
+    assert_eq!("score cp 35", format_score(35, Bound::Exact));
+    assert_eq!("score cp -20 upperbound", format_score(-20, Bound::Upper));
+    assert_eq!("score mate 2", format_score(MATE_SCORE - 3, Bound::Exact));
+    assert_eq!("score mate -1 lowerbound", format_score(-MATE_SCORE + 2, Bound::Lower));
+
+    let mut game = Game::new();
+    let e4 = parse_move(&game, "e2e4").unwrap();
+    e4.make(&mut game);
+    let e5 = parse_move(&game, "e7e5").unwrap();
+    let iteration = Iteration {
+        depth: 2,
+        seldepth: 5,
+        score: 12,
+        bound: Bound::Exact,
+        pv: vec![e4, e5],
+        nodes: 3000,
+        multipv: 1,
+        hashfull: 7,
+    };
+    assert_eq!(
+        "info depth 2 seldepth 5 multipv 1 score cp 12 nodes 3000 nps 20000 time 150 hashfull 7 pv e2e4 e7e5",
+        format_info(&iteration, Duration::from_millis(150))
+    );
+}
+
+#[test]
+fn test_parse_setoption() {
+    match parse_command("setoption name Debug Log File value /tmp/my log") {
+        Some(Command::SetOption { name, value }) => {
+            assert_eq!("Debug Log File", name);
+            assert_eq!(Some("/tmp/my log".to_string()), value);
+        }
+        _ => unreachable!(),
+    }
+    match parse_command("setoption name Clear Hash") {
+        Some(Command::SetOption { name, value }) => {
+            assert_eq!("Clear Hash", name);
+            assert_eq!(None, value);
+        }
+        _ => unreachable!(),
+    }
+    assert!(parse_command("setoption Hash 32").is_none());
+}
+
+#[test]
+fn test_parse_illegal_move() {
+    assert!(parse_command("position startpos moves e2e5").is_none());
+    assert!(parse_command("position startpos moves e7e5").is_none());
+    assert!(parse_command("position startpos moves e2e4x").is_none());
+    // the pinned knight cannot move
+    assert!(parse_command("position startpos moves e2e4 d7d6 d2d4 e8d7 f1b5 b8c6 d4d5 c6e5").is_none());
+    assert!(parse_command("position startpos moves e2e4 d7d6 d2d4 e8d7 f1b5 c7c6 d4d5 d8b6").is_some());
+}
@@ -0,0 +1,168 @@
+use crate::{
+    board::{Color, Piece},
+    game::{CastlingRights, Game},
+    square::Square,
+};
+
+struct Keys {
+    pieces: [[u64; 64]; 12],
+    castling: [u64; 16],
+    en_passant: [u64; 8],
+    black_to_move: u64,
+}
+
+/// splitmix64, returns the new state and the generated number
+const fn next_random(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_keys() -> Keys {
+    let mut keys = Keys {
+        pieces: [[0; 64]; 12],
+        castling: [0; 16],
+        en_passant: [0; 8],
+        black_to_move: 0,
+    };
+    let mut state = 0x2545_f491_4f6c_dd1d;
+    let mut r;
+
+    let mut i = 0;
+    while i < 12 {
+        let mut sq = 0;
+        while sq < 64 {
+            (state, r) = next_random(state);
+            keys.pieces[i][sq] = r;
+            sq += 1;
+        }
+        i += 1;
+    }
+
+    // the rights are independent so the key of a set of rights is the xor of
+    // the keys of each right
+    let mut single = [0; 4];
+    i = 0;
+    while i < 4 {
+        (state, r) = next_random(state);
+        single[i] = r;
+        i += 1;
+    }
+    i = 0;
+    while i < 16 {
+        let mut bit = 0;
+        while bit < 4 {
+            if i & (1 << bit) != 0 {
+                keys.castling[i] ^= single[bit];
+            }
+            bit += 1;
+        }
+        i += 1;
+    }
+
+    i = 0;
+    while i < 8 {
+        (state, r) = next_random(state);
+        keys.en_passant[i] = r;
+        i += 1;
+    }
+
+    (_, keys.black_to_move) = next_random(state);
+
+    keys
+}
+
+const KEYS: Keys = generate_keys();
+
+pub fn piece_key(piece: Piece, square: Square) -> u64 {
+    let color = match piece.color {
+        Color::White => 0,
+        Color::Black => 6,
+    };
+    KEYS.pieces[color + piece.typ as usize][square.index()]
+}
+
+pub fn castling_key(castling_rights: CastlingRights) -> u64 {
+    KEYS.castling[castling_rights as usize & 0b1111]
+}
+
+/// Only the file of the en passant square is hashed
+pub fn en_passant_key(en_passant: Option<Square>) -> u64 {
+    match en_passant {
+        Some(sq) => KEYS.en_passant[sq.file() as usize],
+        None => 0,
+    }
+}
+
+pub fn side_key() -> u64 {
+    KEYS.black_to_move
+}
+
+/// Compute the key of the position from scratch
+pub fn hash(game: &Game) -> u64 {
+    let mut h = 0;
+
+    for rank in 0..8 {
+        for file in 0..8 {
+            let sq = Square::new_nocheck(file, rank);
+            if let Some(p) = game.board.get(sq) {
+                h ^= piece_key(p, sq);
+            }
+        }
+    }
+
+    h ^= castling_key(game.castling_rights);
+    h ^= en_passant_key(game.en_passant);
+
+    if game.player == Color::Black {
+        h ^= side_key();
+    }
+
+    h
+}
+
+#[test]
+fn test_transposition() {
+    use crate::moves::Move;
+
+    let play = |moves: &[(&str, &str)]| {
+        let mut game = Game::new();
+        for (from, to) in moves {
+            Move::new(&game, Square::from_name(from), Square::from_name(to), None).make(&mut game);
+        }
+        game
+    };
+
+    let a = play(&[("g1", "f3"), ("g8", "f6"), ("b1", "c3")]);
+    let b = play(&[("b1", "c3"), ("g8", "f6"), ("g1", "f3")]);
+    assert_eq!(a.hash, b.hash);
+    assert_eq!(a.hash, hash(&a));
+
+    let back = play(&[("g1", "f3"), ("g8", "f6"), ("f3", "g1"), ("f6", "g8")]);
+    assert_eq!(back.hash, Game::new().hash);
+
+    // same pieces but different castling rights
+    let c = play(&[
+        ("g1", "f3"),
+        ("g8", "f6"),
+        ("h1", "g1"),
+        ("f6", "g8"),
+        ("g1", "h1"),
+        ("g8", "f6"),
+    ]);
+    assert_ne!(c.hash, play(&[("g1", "f3"), ("g8", "f6")]).hash);
+}
+
+#[test]
+fn test_en_passant_and_side() {
+    let with_ep = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3";
+    let without_ep = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3";
+    let white = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3";
+    let hash_fen = |fen: &str| Game::from_fen_str(fen).hash;
+
+    assert_ne!(hash_fen(with_ep), hash_fen(without_ep));
+    assert_ne!(hash_fen(without_ep), hash_fen(white));
+    assert_eq!(hash_fen(white) ^ side_key(), hash_fen(without_ep));
+}